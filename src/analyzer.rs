@@ -0,0 +1,265 @@
+use rustfft::num_complex::Complex;
+use rustfft::{Fft, FftPlanner};
+use std::collections::VecDeque;
+use std::sync::Arc;
+
+use crate::window::WindowFunction;
+
+// The level reported for bins with no energy at all, instead of -infinity
+pub const MIN_DB: f32 = -200.0;
+
+// Turns a stream of samples into spectrum frames using a short-time Fourier transform
+// Each frame holds the level of every bin in dBFS, normalized so that a full-scale sine
+// reads 0 dB whatever the FFT size and window
+#[derive(Clone)]
+pub struct SpectrumAnalyzer {
+    fft: Arc<dyn Fft<f32>>,
+    window: Vec<f32>,
+    window_function: WindowFunction,
+    // Samples not yet moved past, the oldest first
+    samples: VecDeque<f32>,
+    buffer: Vec<Complex<f32>>,
+    fft_size: usize,
+    step_size: usize,
+    sample_rate: u32,
+    // Turns FFT magnitudes into amplitudes relative to full scale
+    amplitude_scale: f32,
+}
+
+impl SpectrumAnalyzer {
+    // `fft_size` is the window length and must be a power of 2, a new frame is ready after
+    // every `step_size` samples once the first window is full
+    pub fn new(fft_size: usize, step_size: usize, sample_rate: u32, window_function: WindowFunction) -> Self {
+        assert!(fft_size.is_power_of_two(), "FFT size must be a power of 2");
+        assert!(step_size > 0 && step_size <= fft_size, "Step size must be between 1 and the FFT size");
+
+        let window = window_function.coefficients(fft_size);
+        // A sine of amplitude A shows up in its bin with a magnitude of A times half the sum of the window
+        let window_sum: f32 = window.iter().sum();
+
+        SpectrumAnalyzer {
+            fft: FftPlanner::new().plan_fft_forward(fft_size),
+            window,
+            window_function,
+            samples: VecDeque::with_capacity(2 * fft_size),
+            buffer: vec![Complex::default(); fft_size],
+            fft_size,
+            step_size,
+            sample_rate,
+            amplitude_scale: 2.0 / window_sum,
+        }
+    }
+
+    pub fn fft_size(&self) -> usize {
+        self.fft_size
+    }
+
+    pub fn step_size(&self) -> usize {
+        self.step_size
+    }
+
+    pub fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    pub fn window_function(&self) -> WindowFunction {
+        self.window_function
+    }
+
+    // How far apart frames are, in seconds
+    pub fn frame_interval(&self) -> f32 {
+        self.step_size as f32 / self.sample_rate as f32
+    }
+
+    // When the middle of frame number `index` falls, in seconds from the first sample
+    pub fn frame_center(&self, index: u64) -> f64 {
+        (index as f64 * self.step_size as f64 + self.fft_size as f64 / 2.0) / self.sample_rate as f64
+    }
+
+    // Number of values in each frame, covering 0 Hz up to (just below) the Nyquist frequency
+    pub fn bins(&self) -> usize {
+        self.fft_size / 2
+    }
+
+    pub fn push_samples(&mut self, samples: &[f32]) {
+        self.samples.extend(samples);
+    }
+
+    // Compute the next frame into `out`, which must be `bins()` long
+    // Returns false, leaving `out` alone, if we need more samples first
+    pub fn next_frame(&mut self, out: &mut [f32]) -> bool {
+        if self.samples.len() < self.fft_size {
+            return false;
+        }
+
+        for ((value, sample), weight) in self.buffer.iter_mut().zip(&self.samples).zip(&self.window) {
+            *value = Complex::new(sample * weight, 0.0);
+        }
+        self.fft.process(&mut self.buffer);
+        self.samples.drain(..self.step_size);
+
+        for (value, bin) in out.iter_mut().zip(&self.buffer) {
+            *value = amplitude_to_db(bin.norm() * self.amplitude_scale);
+        }
+        true
+    }
+
+    // How many Hz apart the bins are
+    pub fn bin_width(&self) -> f32 {
+        bin_to_freq(1.0, self.fft_size, self.sample_rate)
+    }
+
+    // The frequency in Hz at the center of `bin`, which may be fractional
+    pub fn bin_to_freq(&self, bin: f32) -> f32 {
+        bin_to_freq(bin, self.fft_size, self.sample_rate)
+    }
+
+    pub fn freq_to_bin(&self, freq: f32) -> f32 {
+        freq_to_bin(freq, self.fft_size, self.sample_rate)
+    }
+}
+
+// Level in dB of an amplitude relative to full scale, never below `MIN_DB`
+pub fn amplitude_to_db(amplitude: f32) -> f32 {
+    (20.0 * amplitude.log10()).max(MIN_DB)
+}
+
+// The frequency in Hz at the center of `bin` of an FFT of `fft_size` samples
+pub fn bin_to_freq(bin: f32, fft_size: usize, sample_rate: u32) -> f32 {
+    bin * (sample_rate as f32) / (fft_size as f32)
+}
+
+// The (fractional) bin of an FFT of `fft_size` samples that `freq` falls in
+pub fn freq_to_bin(freq: f32, fft_size: usize, sample_rate: u32) -> f32 {
+    freq * (fft_size as f32) / (sample_rate as f32)
+}
+
+// The level of `spectrum`, whose bins are `bin_width` Hz apart, at `freq`, interpolated between
+// the bins either side of it
+pub fn level_at(spectrum: &[f32], freq: f32, bin_width: f32) -> f32 {
+    let last_bin = spectrum.len() - 1;
+    let pos = (freq / bin_width).clamp(0.0, last_bin as f32);
+    let below = pos.floor() as usize;
+    let above = (below + 1).min(last_bin);
+    let frac = pos - below as f32;
+    spectrum[below] * (1.0 - frac) + spectrum[above] * frac
+}
+
+// The loudest level of `spectrum`, whose bins are `bin_width` Hz apart, from `low` to `high` Hz,
+// or the level in the middle of them if no bin falls between them
+pub fn band_level(spectrum: &[f32], low: f32, high: f32, bin_width: f32) -> f32 {
+    let first = (low / bin_width).ceil().max(0.0) as usize;
+    let after = ((high / bin_width).floor().max(0.0) as usize + 1).min(spectrum.len());
+    if first < after {
+        spectrum[first..after].iter().fold(f32::MIN, |max, &bin| max.max(bin))
+    } else {
+        level_at(spectrum, (low + high) / 2.0, bin_width)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sine(freq: f32, amplitude: f32, sample_rate: u32, len: usize) -> Vec<f32> {
+        (0..len)
+            .map(|i| amplitude * (2.0 * std::f32::consts::PI * freq * i as f32 / sample_rate as f32).sin())
+            .collect()
+    }
+
+    #[test]
+    fn bins_map_to_frequencies() {
+        assert_eq!(bin_to_freq(0.0, 4096, 48000), 0.0);
+        assert_eq!(bin_to_freq(2048.0, 4096, 48000), 24000.0);
+        assert_eq!(bin_to_freq(1.5, 1024, 1024), 1.5);
+        assert_eq!(freq_to_bin(bin_to_freq(123.0, 4096, 44100), 4096, 44100), 123.0);
+    }
+
+    #[test]
+    fn levels_interpolate_between_bins() {
+        let spectrum = [-60.0, -20.0, -40.0];
+        assert_eq!(level_at(&spectrum, 10.0, 10.0), -20.0);
+        assert_eq!(level_at(&spectrum, 15.0, 10.0), -30.0);
+        // Past either end reads the end bin
+        assert_eq!(level_at(&spectrum, 100.0, 10.0), -40.0);
+        assert_eq!(level_at(&spectrum, -5.0, 10.0), -60.0);
+
+        // Bands take their loudest bin, or the level between the bins either side of them
+        assert_eq!(band_level(&spectrum, 0.0, 25.0, 10.0), -20.0);
+        assert_eq!(band_level(&spectrum, 12.0, 18.0, 10.0), -30.0);
+    }
+
+    #[test]
+    fn frames_need_a_full_window() {
+        let mut analyzer = SpectrumAnalyzer::new(1024, 256, 8000, WindowFunction::Hann);
+        let mut frame = vec![0.0; analyzer.bins()];
+
+        analyzer.push_samples(&vec![0.0; 1023]);
+        assert!(!analyzer.next_frame(&mut frame));
+
+        // One more sample fills the window, then every step gives another frame
+        analyzer.push_samples(&vec![0.0; 1 + 2 * 256]);
+        let mut frames = 0;
+        while analyzer.next_frame(&mut frame) {
+            frames += 1;
+        }
+        assert_eq!(frames, 3);
+    }
+
+    #[test]
+    fn sine_peaks_in_its_bin() {
+        let sample_rate = 48000;
+        let mut analyzer = SpectrumAnalyzer::new(4096, 1024, sample_rate, WindowFunction::Hann);
+        let mut frame = vec![0.0; analyzer.bins()];
+
+        // Put the sine right in the middle of bin 100
+        let freq = analyzer.bin_to_freq(100.0);
+        analyzer.push_samples(&sine(freq, 1.0, sample_rate, 4096));
+        assert!(analyzer.next_frame(&mut frame));
+
+        let peak = (0..frame.len())
+            .max_by(|&a, &b| frame[a].partial_cmp(&frame[b]).unwrap())
+            .unwrap();
+        assert_eq!(peak, 100);
+    }
+
+    #[test]
+    fn levels_are_relative_to_full_scale() {
+        for (fft_size, window) in [(1024, WindowFunction::Hann), (4096, WindowFunction::BlackmanHarris)] {
+            let sample_rate = 48000;
+            let mut analyzer = SpectrumAnalyzer::new(fft_size, fft_size, sample_rate, window);
+            let mut frame = vec![0.0; analyzer.bins()];
+
+            let freq = analyzer.bin_to_freq(50.0);
+            analyzer.push_samples(&sine(freq, 1.0, sample_rate, fft_size));
+            analyzer.push_samples(&sine(freq, 0.1, sample_rate, fft_size));
+
+            assert!(analyzer.next_frame(&mut frame));
+            assert!(frame[50].abs() < 0.1, "full scale read {} dB", frame[50]);
+            assert!(analyzer.next_frame(&mut frame));
+            assert!((frame[50] + 20.0).abs() < 0.1, "-20 dB read {} dB", frame[50]);
+        }
+    }
+
+    #[test]
+    fn flat_top_reads_levels_between_bins() {
+        let sample_rate = 48000;
+        let mut analyzer = SpectrumAnalyzer::new(4096, 4096, sample_rate, WindowFunction::FlatTop);
+        let mut frame = vec![0.0; analyzer.bins()];
+
+        // Halfway between bins is the worst case, a Hann window would read 1.4 dB low here
+        analyzer.push_samples(&sine(analyzer.bin_to_freq(100.5), 0.5, sample_rate, 4096));
+        assert!(analyzer.next_frame(&mut frame));
+        let peak = frame.iter().fold(MIN_DB, |max, &value| max.max(value));
+        assert!((peak - amplitude_to_db(0.5)).abs() < 0.05, "read {} dB", peak);
+    }
+
+    #[test]
+    fn silence_reads_min_db() {
+        let mut analyzer = SpectrumAnalyzer::new(256, 256, 8000, WindowFunction::Hann);
+        let mut frame = vec![0.0; analyzer.bins()];
+        analyzer.push_samples(&[0.0; 256]);
+        assert!(analyzer.next_frame(&mut frame));
+        assert!(frame.iter().all(|&value| value == MIN_DB));
+    }
+}
@@ -0,0 +1,361 @@
+use std::str::FromStr;
+
+use crate::analyzer::level_at;
+use crate::pitch::Note;
+
+// How frequencies are spread across the plot
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum FrequencyScale {
+    Linear,
+    Log,
+    // Log too, but labelled with note names
+    Note,
+}
+
+impl FromStr for FrequencyScale {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "linear" => Ok(FrequencyScale::Linear),
+            "log" => Ok(FrequencyScale::Log),
+            "note" => Ok(FrequencyScale::Note),
+            _ => Err(format!("Unknown frequency axis \"{}\", expected linear, log or note", s)),
+        }
+    }
+}
+
+// Maps between frequencies and positions along a plot, where 0 is the left edge at `min` Hz
+// and 1 is the right edge at `max` Hz
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct FrequencyAxis {
+    pub scale: FrequencyScale,
+    pub min: f32,
+    pub max: f32,
+}
+
+impl FrequencyAxis {
+    // `min` must be above 0 for a log scale
+    pub fn new(scale: FrequencyScale, min: f32, max: f32) -> Self {
+        assert!(min < max, "Frequency range must not be empty");
+        assert!(scale == FrequencyScale::Linear || min > 0.0, "Log frequency axis must start above 0 Hz");
+
+        FrequencyAxis { scale, min, max }
+    }
+
+    // Where `freq` goes along the axis, outside of 0 to 1 if it's out of range
+    pub fn position(&self, freq: f32) -> f32 {
+        match self.scale {
+            FrequencyScale::Linear => (freq - self.min) / (self.max - self.min),
+            FrequencyScale::Log | FrequencyScale::Note => (freq / self.min).ln() / (self.max / self.min).ln(),
+        }
+    }
+
+    // The frequency at `position` along the axis
+    pub fn freq_at(&self, position: f32) -> f32 {
+        match self.scale {
+            FrequencyScale::Linear => self.min + position * (self.max - self.min),
+            FrequencyScale::Log | FrequencyScale::Note => self.min * (self.max / self.min).powf(position),
+        }
+    }
+
+    // Zoom in on the frequency at `position`, which stays where it is, so the axis covers `factor`
+    // times as much (zooming out for a `factor` over 1), staying between `lowest` and `highest` Hz
+    pub fn zoom(&self, position: f32, factor: f32, lowest: f32, highest: f32) -> Self {
+        let (min, max) = (self.warp(self.min), self.warp(self.max));
+        let at = min + position * (max - min);
+        let span = (max - min) * factor;
+        self.fit(at - position * span, span, lowest, highest)
+    }
+
+    // Move the frequencies along by `distance` of the axis' length, to the right if it's positive,
+    // staying between `lowest` and `highest` Hz
+    pub fn pan(&self, distance: f32, lowest: f32, highest: f32) -> Self {
+        let (min, max) = (self.warp(self.min), self.warp(self.max));
+        self.fit(min - distance * (max - min), max - min, lowest, highest)
+    }
+
+    // The axis covering `span` from `start`, both warped, moved back between `lowest` and
+    // `highest` Hz if it's gone past either. Left as it is if that's narrower than 1 Hz
+    fn fit(&self, start: f32, span: f32, lowest: f32, highest: f32) -> Self {
+        let (warped_lowest, warped_highest) = (self.warp(lowest), self.warp(highest));
+        // The whole range, without rounding through the warp
+        let (min, max) = if span >= warped_highest - warped_lowest {
+            (lowest, highest)
+        } else {
+            let start = start.clamp(warped_lowest, (warped_highest - span).max(warped_lowest));
+            (self.unwarp(start), self.unwarp(start + span))
+        };
+        if max - min < 1.0 {
+            return *self;
+        }
+        FrequencyAxis::new(self.scale, min, max)
+    }
+
+    // Where positions along the axis are evenly spread
+    fn warp(&self, freq: f32) -> f32 {
+        match self.scale {
+            FrequencyScale::Linear => freq,
+            FrequencyScale::Log | FrequencyScale::Note => freq.ln(),
+        }
+    }
+
+    fn unwarp(&self, value: f32) -> f32 {
+        match self.scale {
+            FrequencyScale::Linear => value,
+            FrequencyScale::Log | FrequencyScale::Note => value.exp(),
+        }
+    }
+
+    // Frequencies worth putting a labelled tick at
+    // Linear axes get 20 evenly spaced divisions, log axes get 1, 2 and 5 times each power of 10
+    // Note axes are better off with `note_ticks`
+    pub fn ticks(&self) -> Vec<f32> {
+        match self.scale {
+            FrequencyScale::Linear => {
+                let num_ticks = 20;
+                (0..=num_ticks)
+                    .map(|i| self.freq_at((i as f32) / (num_ticks as f32)))
+                    .collect()
+            }
+            FrequencyScale::Log | FrequencyScale::Note => {
+                let mut ticks = Vec::new();
+                for decade in (self.min.log10().floor() as i32)..=(self.max.log10().ceil() as i32) {
+                    for multiple in [1.0, 2.0, 5.0] {
+                        let freq = multiple * 10f32.powi(decade);
+                        if freq >= self.min && freq <= self.max {
+                            ticks.push(freq);
+                        }
+                    }
+                }
+                ticks
+            }
+        }
+    }
+
+    // Label for a tick at `freq` in Hz
+    pub fn tick_label(&self, freq: f32) -> String {
+        match self.scale {
+            FrequencyScale::Linear => format!("{:.0}", freq),
+            FrequencyScale::Log | FrequencyScale::Note if freq >= 1000.0 => format!("{}k", freq / 1000.0),
+            FrequencyScale::Log | FrequencyScale::Note => format!("{}", freq),
+        }
+    }
+
+    // Every note on the axis, with A4 at `a4` Hz, from the lowest up. The axis has to start above
+    // 0 Hz, like log and note axes do
+    pub fn notes(&self, a4: f32) -> Vec<Note> {
+        let (lowest, _) = Note::nearest(self.min, a4);
+        let (highest, _) = Note::nearest(self.max, a4);
+        (lowest.0..=highest.0)
+            .map(Note)
+            .filter(|note| (self.min..=self.max).contains(&note.freq(a4)))
+            .collect()
+    }
+
+    // Notes worth putting a labelled tick at: every one if there are few enough, otherwise every
+    // third (the Cs, D#s, F#s and As), or just the Cs
+    pub fn note_ticks(&self, a4: f32) -> Vec<Note> {
+        let notes = self.notes(a4);
+        let step = [1, 3].into_iter().find(|&step| notes.len() / step <= 24).unwrap_or(12);
+        notes.into_iter().filter(|note| note.0.rem_euclid(step as i32) == 0).collect()
+    }
+
+    // Resample `spectrum`, whose bins are `bin_width` Hz apart, onto `out.len()` points spread
+    // evenly along the axis, each point covering the frequencies up to the next one
+    // Where a point covers several bins it takes the biggest so narrow peaks don't vanish,
+    // otherwise it interpolates between the bins around it
+    pub fn resample(&self, spectrum: &[f32], bin_width: f32, out: &mut [f32]) {
+        let points = out.len() as f32;
+        let last_bin = spectrum.len() - 1;
+
+        for (i, value) in out.iter_mut().enumerate() {
+            let start = self.freq_at(i as f32 / points) / bin_width;
+            let end = self.freq_at((i + 1) as f32 / points) / bin_width;
+
+            if end - start >= 1.0 {
+                let first = (start.ceil().max(0.0) as usize).min(last_bin);
+                let after = (end.ceil().max(0.0) as usize).clamp(first + 1, spectrum.len());
+                *value = spectrum[first..after].iter().fold(f32::MIN, |max, &bin| max.max(bin));
+            } else {
+                *value = level_at(spectrum, start * bin_width, bin_width);
+            }
+        }
+    }
+}
+
+// Maps levels in dB to positions up a plot, where 0 is the bottom at `floor` and 1 is the top at `ceiling`
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct LevelAxis {
+    pub floor: f32,
+    pub ceiling: f32,
+}
+
+impl LevelAxis {
+    pub fn new(floor: f32, ceiling: f32) -> Self {
+        assert!(floor.is_finite() && ceiling.is_finite(), "Level floor and ceiling must be finite");
+        assert!(floor < ceiling, "Level floor must be below the ceiling");
+
+        LevelAxis { floor, ceiling }
+    }
+
+    // Where `db` goes up the axis, outside of 0 to 1 if it's out of range
+    pub fn position(&self, db: f32) -> f32 {
+        (db - self.floor) / (self.ceiling - self.floor)
+    }
+
+    // The level at `position` up the axis
+    pub fn level_at(&self, position: f32) -> f32 {
+        self.floor + position * (self.ceiling - self.floor)
+    }
+
+    // Levels worth a labelled gridline, on round multiples of a step picked so there are
+    // never more than about a dozen. None at all if the axis doesn't end, it'd never stop
+    pub fn ticks(&self) -> Vec<f32> {
+        let range = self.ceiling - self.floor;
+        if !range.is_finite() {
+            return Vec::new();
+        }
+        let step = [1.0, 2.0, 5.0, 10.0, 20.0, 50.0]
+            .into_iter()
+            .find(|step| range / step <= 12.0)
+            .unwrap_or(100.0);
+
+        let mut ticks = Vec::new();
+        let mut tick = (self.floor / step).ceil() * step;
+        while tick <= self.ceiling {
+            ticks.push(tick);
+            tick += step;
+        }
+        ticks
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn positions_round_trip() {
+        for scale in [FrequencyScale::Linear, FrequencyScale::Log, FrequencyScale::Note] {
+            let axis = FrequencyAxis::new(scale, 20.0, 20000.0);
+            assert_eq!(axis.position(20.0), 0.0);
+            assert!((axis.position(20000.0) - 1.0).abs() < 1e-6);
+            assert!((axis.freq_at(axis.position(440.0)) - 440.0).abs() < 1e-2);
+        }
+    }
+
+    #[test]
+    fn log_axis_spreads_decades_evenly() {
+        let axis = FrequencyAxis::new(FrequencyScale::Log, 10.0, 10000.0);
+        assert!((axis.position(100.0) - 1.0 / 3.0).abs() < 1e-6);
+        assert!((axis.position(1000.0) - 2.0 / 3.0).abs() < 1e-6);
+    }
+
+    #[test]
+    fn log_ticks_step_one_two_five() {
+        let axis = FrequencyAxis::new(FrequencyScale::Log, 20.0, 12000.0);
+        assert_eq!(
+            axis.ticks(),
+            vec![20.0, 50.0, 100.0, 200.0, 500.0, 1000.0, 2000.0, 5000.0, 10000.0]
+        );
+        assert_eq!(axis.tick_label(2000.0), "2k");
+        assert_eq!(axis.tick_label(50.0), "50");
+    }
+
+    #[test]
+    fn note_ticks_thin_out_with_more_octaves() {
+        // An octave from just under middle C gets every note
+        let axis = FrequencyAxis::new(FrequencyScale::Note, 250.0, 500.0);
+        let names: Vec<_> = axis.note_ticks(440.0).iter().map(|note| note.to_string()).collect();
+        assert_eq!(names, ["C4", "C#4", "D4", "D#4", "E4", "F4", "F#4", "G4", "G#4", "A4", "A#4", "B4"]);
+
+        let axis = FrequencyAxis::new(FrequencyScale::Note, 100.0, 1000.0);
+        let names: Vec<_> = axis.note_ticks(440.0).iter().take(4).map(|note| note.to_string()).collect();
+        assert_eq!(names, ["A2", "C3", "D#3", "F#3"]);
+
+        let axis = FrequencyAxis::new(FrequencyScale::Note, 20.0, 20000.0);
+        let ticks = axis.note_ticks(440.0);
+        assert_eq!(ticks.len(), 10);
+        assert!(ticks.iter().all(|note| note.name() == "C"));
+        // Placed where the notes are on a log axis
+        let a4 = FrequencyAxis::new(FrequencyScale::Note, 220.0, 880.0).position(Note(69).freq(440.0));
+        assert!((a4 - 0.5).abs() < 1e-6);
+    }
+
+    #[test]
+    fn zoom_and_pan_stay_in_range() {
+        let axis = FrequencyAxis::new(FrequencyScale::Linear, 0.0, 1000.0);
+        // Around 750 Hz, which stays three quarters of the way along
+        let zoomed = axis.zoom(0.75, 0.5, 0.0, 1000.0);
+        assert_eq!((zoomed.min, zoomed.max), (375.0, 875.0));
+        assert_eq!(zoomed.position(750.0), 0.75);
+        // Dragging the frequencies right shows lower ones, but never below `lowest`
+        let panned = zoomed.pan(0.5, 0.0, 1000.0);
+        assert_eq!((panned.min, panned.max), (125.0, 625.0));
+        let panned = panned.pan(1.0, 0.0, 1000.0);
+        assert_eq!((panned.min, panned.max), (0.0, 500.0));
+        // And zooming out stops at the whole range
+        let out = panned.zoom(0.5, 10.0, 0.0, 1000.0);
+        assert_eq!((out.min, out.max), (0.0, 1000.0));
+
+        let log = FrequencyAxis::new(FrequencyScale::Log, 10.0, 10000.0);
+        let zoomed = log.zoom(0.5, 1.0 / 3.0, 10.0, 10000.0);
+        // The middle decade of three
+        assert!((zoomed.min - 100.0).abs() < 1e-3);
+        assert!((zoomed.max - 1000.0).abs() < 1e-2);
+    }
+
+    #[test]
+    fn zoomed_out_log_axes_pan_and_zoom_without_rounding_past_the_range() {
+        for (lowest, highest) in [(10.0, 48000.0), (12.3, 44100.0), (12.3, 48000.0), (10.0, 44100.0), (15.0, 96000.0)] {
+            let axis = FrequencyAxis::new(FrequencyScale::Log, lowest, highest);
+            let moved = [
+                axis.pan(0.05, lowest, highest),
+                axis.pan(-0.05, lowest, highest),
+                axis.zoom(0.5, 1.25, lowest, highest),
+            ];
+            for moved in moved {
+                assert_eq!((moved.min, moved.max), (lowest, highest));
+            }
+        }
+    }
+
+    #[test]
+    fn level_ticks_land_on_round_numbers() {
+        let axis = LevelAxis::new(-95.0, 0.0);
+        assert_eq!(axis.ticks(), vec![-90.0, -80.0, -70.0, -60.0, -50.0, -40.0, -30.0, -20.0, -10.0, 0.0]);
+        assert_eq!(axis.position(-95.0), 0.0);
+        assert_eq!(axis.position(0.0), 1.0);
+        assert_eq!(axis.level_at(axis.position(-40.0)), -40.0);
+        assert_eq!(LevelAxis::new(-12.0, 0.0).ticks().len(), 13);
+
+        // Only built by hand, `new` won't have it
+        let endless = LevelAxis { floor: f32::NEG_INFINITY, ceiling: 0.0 };
+        assert!(endless.ticks().is_empty());
+    }
+
+    #[test]
+    #[should_panic(expected = "must be finite")]
+    fn level_axes_must_end() {
+        LevelAxis::new(f32::NEG_INFINITY, 0.0);
+    }
+
+    #[test]
+    fn linear_resample_keeps_bins() {
+        let spectrum = [0.0, 1.0, 2.0, 3.0];
+        let axis = FrequencyAxis::new(FrequencyScale::Linear, 0.0, 40.0);
+        let mut out = [0.0; 4];
+        axis.resample(&spectrum, 10.0, &mut out);
+        assert_eq!(out, spectrum);
+    }
+
+    #[test]
+    fn resample_keeps_peaks() {
+        let spectrum = [0.0, 0.0, 5.0, 0.0, 0.0, 0.0, 0.0, 0.0];
+        let axis = FrequencyAxis::new(FrequencyScale::Linear, 0.0, 8.0);
+        let mut out = [0.0; 2];
+        axis.resample(&spectrum, 1.0, &mut out);
+        assert_eq!(out, [5.0, 0.0]);
+    }
+}
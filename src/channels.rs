@@ -0,0 +1,235 @@
+use std::fmt;
+use std::str::FromStr;
+
+use crate::analyzer::SpectrumAnalyzer;
+
+// Which of an input's channels get analysed, and how
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ChannelMode {
+    // Average every channel into one signal
+    Mix,
+    // Just the one channel, counting from 0
+    Single(usize),
+    // Every channel as a signal of its own
+    Separate,
+}
+
+impl ChannelMode {
+    // How many signals come out of an input with `channels` channels
+    pub fn signals(self, channels: usize) -> usize {
+        match self {
+            ChannelMode::Separate => channels.max(1),
+            ChannelMode::Mix | ChannelMode::Single(_) => 1,
+        }
+    }
+
+    // Mix, then separate, then each channel on its own, then back to mix
+    // There's nothing to pick between with a single channel
+    pub fn next(self, channels: usize) -> Self {
+        if channels < 2 {
+            return self;
+        }
+        match self {
+            ChannelMode::Mix => ChannelMode::Separate,
+            ChannelMode::Separate => ChannelMode::Single(0),
+            ChannelMode::Single(channel) if channel + 1 < channels => ChannelMode::Single(channel + 1),
+            ChannelMode::Single(_) => ChannelMode::Mix,
+        }
+    }
+
+    // Append the signals in `samples`, interleaved by `channels`, to `signals`, which must hold
+    // `signals(channels)` of them
+    // A channel past the last one the input has falls back to its last
+    pub fn split(self, samples: &[f32], channels: usize, signals: &mut [Vec<f32>]) {
+        let channels = channels.max(1);
+        let frames = samples.chunks_exact(channels);
+        match self {
+            ChannelMode::Mix => {
+                signals[0].extend(frames.map(|frame| frame.iter().sum::<f32>() / channels as f32));
+            }
+            ChannelMode::Single(channel) => {
+                let channel = channel.min(channels - 1);
+                signals[0].extend(frames.map(|frame| frame[channel]));
+            }
+            ChannelMode::Separate => {
+                for frame in frames {
+                    for (signal, sample) in signals.iter_mut().zip(frame) {
+                        signal.push(*sample);
+                    }
+                }
+            }
+        }
+    }
+}
+
+impl FromStr for ChannelMode {
+    type Err = String;
+
+    // Channels are numbered from 1 here, like most audio software does
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "mix" => Ok(ChannelMode::Mix),
+            "separate" => Ok(ChannelMode::Separate),
+            _ => match s.parse::<usize>() {
+                Ok(channel) if channel > 0 => Ok(ChannelMode::Single(channel - 1)),
+                _ => Err(format!("Unknown channels \"{}\", expected mix, separate or a channel number", s)),
+            },
+        }
+    }
+}
+
+impl fmt::Display for ChannelMode {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ChannelMode::Mix => write!(f, "mix"),
+            ChannelMode::Single(channel) => write!(f, "{}", channel + 1),
+            ChannelMode::Separate => write!(f, "separate"),
+        }
+    }
+}
+
+// Analyses each of the signals a `ChannelMode` picks out of an interleaved input with its own
+// `SpectrumAnalyzer`, all sharing the same settings so their frames line up
+pub struct MultiChannelAnalyzer {
+    mode: ChannelMode,
+    channels: usize,
+    analyzers: Vec<SpectrumAnalyzer>,
+    // Samples of a frame that hasn't been completed yet
+    pending: Vec<f32>,
+    // Reused for splitting the input
+    signals: Vec<Vec<f32>>,
+}
+
+impl MultiChannelAnalyzer {
+    // Copies `analyzer` for every signal
+    pub fn new(mode: ChannelMode, channels: usize, analyzer: SpectrumAnalyzer) -> Self {
+        let signals = mode.signals(channels);
+        MultiChannelAnalyzer {
+            mode,
+            channels,
+            analyzers: vec![analyzer; signals],
+            pending: Vec::new(),
+            signals: vec![Vec::new(); signals],
+        }
+    }
+
+    pub fn mode(&self) -> ChannelMode {
+        self.mode
+    }
+
+    // How many channels the input has
+    pub fn channels(&self) -> usize {
+        self.channels
+    }
+
+    // How many spectra each call to `next_frames` gives
+    pub fn signals(&self) -> usize {
+        self.analyzers.len()
+    }
+
+    // The analyzers only differ in what they're fed, so any of them can answer questions
+    // about bins and frequencies
+    pub fn first(&self) -> &SpectrumAnalyzer {
+        &self.analyzers[0]
+    }
+
+    pub fn bins(&self) -> usize {
+        self.first().bins()
+    }
+
+    pub fn bin_width(&self) -> f32 {
+        self.first().bin_width()
+    }
+
+    pub fn sample_rate(&self) -> u32 {
+        self.first().sample_rate()
+    }
+
+    // `samples` are interleaved by channel, and don't have to end on a whole frame
+    pub fn push_samples(&mut self, samples: &[f32]) {
+        self.pending.extend_from_slice(samples);
+        let whole_frames = self.pending.len() - self.pending.len() % self.channels.max(1);
+
+        for signal in self.signals.iter_mut() {
+            signal.clear();
+        }
+        self.mode.split(&self.pending[..whole_frames], self.channels, &mut self.signals);
+        self.pending.drain(..whole_frames);
+        for (analyzer, signal) in self.analyzers.iter_mut().zip(&self.signals) {
+            analyzer.push_samples(signal);
+        }
+    }
+
+    // Compute the next frame of every signal into `out`, which must hold `signals()` spectra
+    // `bins()` long. Every signal gets the same samples, so they're all ready at once
+    pub fn next_frames(&mut self, out: &mut [Vec<f32>]) -> bool {
+        let mut ready = true;
+        for (analyzer, spectrum) in self.analyzers.iter_mut().zip(out.iter_mut()) {
+            ready &= analyzer.next_frame(spectrum);
+        }
+        ready
+    }
+}
+
+// The loudest level of each bin across `spectra`
+pub fn loudest(spectra: &[Vec<f32>]) -> Vec<f32> {
+    let mut out = spectra[0].clone();
+    for spectrum in &spectra[1..] {
+        for (value, other) in out.iter_mut().zip(spectrum) {
+            *value = value.max(*other);
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::WindowFunction;
+
+    #[test]
+    fn stereo_splits_by_mode() {
+        let stereo = [1.0, 0.0, 0.5, -0.5, 0.25, 0.75];
+
+        let mut mixed = vec![Vec::new()];
+        ChannelMode::Mix.split(&stereo, 2, &mut mixed);
+        assert_eq!(mixed, vec![vec![0.5, 0.0, 0.5]]);
+
+        let mut right = vec![Vec::new()];
+        ChannelMode::Single(1).split(&stereo, 2, &mut right);
+        assert_eq!(right, vec![vec![0.0, -0.5, 0.75]]);
+
+        let mut separate = vec![Vec::new(), Vec::new()];
+        ChannelMode::Separate.split(&stereo, 2, &mut separate);
+        assert_eq!(separate, vec![vec![1.0, 0.5, 0.25], vec![0.0, -0.5, 0.75]]);
+    }
+
+    #[test]
+    fn modes_parse_with_channels_from_1() {
+        assert_eq!("2".parse(), Ok(ChannelMode::Single(1)));
+        assert_eq!("separate".parse(), Ok(ChannelMode::Separate));
+        assert_eq!(ChannelMode::Single(0).to_string(), "1");
+        assert!("0".parse::<ChannelMode>().is_err());
+        assert_eq!(ChannelMode::Separate.next(2), ChannelMode::Single(0));
+        assert_eq!(ChannelMode::Single(1).next(2), ChannelMode::Mix);
+    }
+
+    #[test]
+    fn separate_channels_get_their_own_spectrum() {
+        // A loud left channel and a silent right one
+        let analyzer = SpectrumAnalyzer::new(256, 256, 256, WindowFunction::Hann);
+        let mut multi = MultiChannelAnalyzer::new(ChannelMode::Separate, 2, analyzer);
+        let samples: Vec<f32> = (0..256)
+            .flat_map(|i| [(2.0 * std::f32::consts::PI * 32.0 * i as f32 / 256.0).sin(), 0.0])
+            .collect();
+        multi.push_samples(&samples[..256]);
+
+        let mut out = vec![vec![0.0; multi.bins()]; multi.signals()];
+        assert!(!multi.next_frames(&mut out));
+        multi.push_samples(&samples[256..]);
+        assert!(multi.next_frames(&mut out));
+        assert!(out[0][32] > -7.0);
+        assert!(out[1][32] < -100.0);
+        assert_eq!(loudest(&out)[32], out[0][32]);
+    }
+}
@@ -0,0 +1,432 @@
+use clap::{CommandFactory, ErrorKind, Parser};
+use live_spectrum::source::{DeviceSelector, Signal};
+use live_spectrum::{ChannelMode, FrequencyScale, TraceKind, TraceSet, WindowFunction};
+use std::path::PathBuf;
+
+use crate::config::Config;
+use crate::waterfall::{ColorMap, ViewMode};
+use crate::{MAX_FFT_SIZE, MIN_FFT_SIZE};
+
+// What to analyse
+pub enum InputChoice {
+    Mic(DeviceSelector),
+    File(PathBuf),
+    Generator(Signal),
+}
+
+// Everything that can be chosen on the command line
+// Settings that can also go in the config file are left as None unless they're given, so the file
+// (or the default) is used for them
+#[derive(Parser)]
+#[clap(version, about = "Shows the spectrum of live or recorded audio", allow_negative_numbers = true)]
+pub struct Options {
+    #[clap(value_name = "FILE", help = "Audio file to play back, instead of listening to the default microphone")]
+    file: Option<PathBuf>,
+
+    #[clap(
+        long = "gen",
+        value_name = "SIGNAL",
+        conflicts_with = "file",
+        help = "Generate a test signal instead: sine:<hz>, chirp:<start hz>-<end hz>:<seconds>, \
+                logchirp:<start hz>-<end hz>:<seconds>, white, pink or tones:<hz>[@<amplitude>],..."
+    )]
+    generator: Option<Signal>,
+
+    #[clap(
+        long,
+        value_name = "NAME|INDEX",
+        conflicts_with_all = &["file", "generator"],
+        help = "Input device to listen to, by (part of) its name or its number in --list-devices [default: the \
+                default input]"
+    )]
+    device: Option<DeviceSelector>,
+
+    #[clap(long, help = "List every input device with the configs it supports, then exit")]
+    pub list_devices: bool,
+
+    #[clap(
+        long,
+        value_name = "FILE",
+        help = "Config file to read settings from, and watch for changes [default: config.toml in the \
+                live_spectrum folder of your config directory, e.g. ~/.config/live_spectrum/config.toml]"
+    )]
+    pub config: Option<PathBuf>,
+
+    #[clap(
+        long,
+        value_name = "SAMPLES",
+        value_parser = parse_fft_size,
+        help_heading = "ANALYSIS",
+        help = "FFT length, a power of 2 from 256 to 32768 [default: 4096]"
+    )]
+    fft_size: Option<usize>,
+
+    #[clap(
+        long = "hop",
+        value_name = "SAMPLES",
+        help_heading = "ANALYSIS",
+        help = "How far the FFT moves along for each spectrum, at most the FFT size [default: 1024]"
+    )]
+    step_size: Option<usize>,
+
+    #[clap(
+        long,
+        help_heading = "ANALYSIS",
+        help = "Window function: rectangular, hann, hamming, blackman, blackman-harris or flat-top [default: hann]"
+    )]
+    window: Option<WindowFunction>,
+
+    #[clap(
+        long,
+        value_name = "MIX|SEPARATE|CHANNEL",
+        help_heading = "ANALYSIS",
+        help = "How to analyse an input with several channels: mix them together, analyse each separately, or \
+                only the one numbered (from 1) [default: mix]"
+    )]
+    channels: Option<ChannelMode>,
+
+    #[clap(
+        long,
+        value_name = "LIST",
+        help_heading = "ANALYSIS",
+        help = "Traces to show, separated by commas: raw, envelope, exponential, linear, max-hold, min-hold, or \
+                none. In headless mode the raw trace is always written [default: envelope]"
+    )]
+    traces: Option<TraceSet>,
+
+    #[clap(
+        long,
+        value_name = "SECONDS",
+        value_parser = parse_time,
+        help_heading = "ANALYSIS",
+        help = "How quickly the envelope rises to louder levels, as a time constant, 0 for straight away [default: 0]"
+    )]
+    attack: Option<f32>,
+
+    #[clap(
+        long,
+        value_name = "SECONDS",
+        value_parser = parse_time,
+        help_heading = "ANALYSIS",
+        help = "How slowly the envelope falls back after a peak, as a time constant [default: 0.3]"
+    )]
+    release: Option<f32>,
+
+    #[clap(
+        long,
+        value_name = "SECONDS",
+        value_parser = parse_time,
+        help_heading = "ANALYSIS",
+        help = "Time constant of the exponential average [default: 1]"
+    )]
+    average_time: Option<f32>,
+
+    #[clap(
+        long,
+        value_name = "FRAMES",
+        value_parser = clap::value_parser!(u32).range(1..),
+        help_heading = "ANALYSIS",
+        help = "How many frames the linear average covers [default: 16]"
+    )]
+    average_frames: Option<u32>,
+
+    #[clap(
+        long,
+        value_name = "HZ",
+        value_parser = parse_number,
+        help_heading = "DISPLAY",
+        help = "Lowest frequency shown [default: 0 Hz, or 20 Hz on a log or note axis]"
+    )]
+    min_freq: Option<f32>,
+
+    #[clap(
+        long,
+        value_name = "HZ",
+        value_parser = parse_number,
+        help_heading = "DISPLAY",
+        help = "Highest frequency shown [default: half the sample rate]"
+    )]
+    max_freq: Option<f32>,
+
+    #[clap(
+        long,
+        value_name = "SCALE",
+        help_heading = "DISPLAY",
+        help = "Frequency axis: linear, log or note (log, labelled with note names) [default: linear]"
+    )]
+    axis: Option<FrequencyScale>,
+
+    #[clap(
+        long,
+        value_name = "DB",
+        value_parser = parse_number,
+        help_heading = "DISPLAY",
+        help = "Lowest level shown, in dBFS [default: -100]"
+    )]
+    floor: Option<f32>,
+
+    #[clap(
+        long,
+        value_name = "DB",
+        value_parser = parse_number,
+        help_heading = "DISPLAY",
+        help = "Highest level shown, in dBFS [default: 0]"
+    )]
+    ceiling: Option<f32>,
+
+    #[clap(
+        long,
+        value_name = "COUNT",
+        help_heading = "DISPLAY",
+        help = "How many of the loudest peaks to label on each trace, up to 10 [default: 5]"
+    )]
+    peaks: Option<usize>,
+
+    #[clap(
+        long,
+        value_name = "DB",
+        value_parser = parse_number,
+        help_heading = "DISPLAY",
+        help = "How loud a peak has to be to be labelled, in dBFS [default: -80]"
+    )]
+    peak_threshold: Option<f32>,
+
+    #[clap(
+        long,
+        value_name = "TRACE",
+        help_heading = "DISPLAY",
+        help = "The trace to label peaks on besides the raw spectrum, while it's shown [default: envelope]"
+    )]
+    peak_trace: Option<TraceKind>,
+
+    #[clap(long, help_heading = "DISPLAY", help = "Show the tuner, with the note nearest the fundamental")]
+    tuner: bool,
+
+    #[clap(
+        long,
+        value_name = "HZ",
+        value_parser = parse_number,
+        help_heading = "DISPLAY",
+        help = "The frequency of A4 the tuner's notes are tuned to [default: 440]"
+    )]
+    a4: Option<f32>,
+
+    #[clap(long, help_heading = "DISPLAY", help = "Show a piano keyboard under a note axis, lighting up the keys being played")]
+    keyboard: bool,
+
+    #[clap(
+        long,
+        value_name = "DB",
+        value_parser = parse_number,
+        help_heading = "DISPLAY",
+        help = "How loud a key's frequencies have to be to light it up, in dBFS [default: -50]"
+    )]
+    keyboard_threshold: Option<f32>,
+
+    #[clap(long, help_heading = "DISPLAY", help = "View: line, waterfall or both [default: line]")]
+    view: Option<ViewMode>,
+
+    #[clap(
+        long,
+        value_name = "MAP",
+        help_heading = "DISPLAY",
+        help = "Waterfall color map: viridis, magma, inferno or grayscale [default: viridis]"
+    )]
+    colormap: Option<ColorMap>,
+
+    #[clap(
+        long,
+        value_name = "OUTPUT.CSV",
+        help_heading = "EXPORT",
+        help = "Don't open a window, write every spectrum to a CSV file instead"
+    )]
+    pub headless: Option<PathBuf>,
+
+    #[clap(
+        long,
+        value_name = "SECONDS",
+        requires = "headless",
+        value_parser = parse_duration,
+        help_heading = "EXPORT",
+        help = "How much input to analyse, needed unless the input is a file"
+    )]
+    pub duration: Option<f32>,
+}
+
+impl Options {
+    pub fn input(&self) -> InputChoice {
+        match (&self.file, &self.generator) {
+            (Some(path), _) => InputChoice::File(path.clone()),
+            (None, Some(signal)) => InputChoice::Generator(signal.clone()),
+            (None, None) => InputChoice::Mic(self.device.clone().unwrap_or(DeviceSelector::Default)),
+        }
+    }
+
+    // Override the settings in `config` with any given on the command line
+    pub fn apply(&self, config: &mut Config) {
+        let analysis = &mut config.analysis;
+        override_with(&mut analysis.fft_size, self.fft_size);
+        override_with(&mut analysis.step_size, self.step_size);
+        override_with(&mut analysis.window, self.window);
+        override_with(&mut analysis.channels, self.channels);
+
+        let display = &mut config.display;
+        override_with(&mut display.traces, self.traces);
+        override_with(&mut display.envelope_attack, self.attack);
+        override_with(&mut display.envelope_release, self.release);
+        override_with(&mut display.average_time, self.average_time);
+        override_with(&mut display.average_frames, self.average_frames.map(|frames| frames as usize));
+        override_with(&mut display.axis, self.axis);
+        display.min_freq = self.min_freq.or(display.min_freq);
+        display.max_freq = self.max_freq.or(display.max_freq);
+        override_with(&mut display.floor, self.floor);
+        override_with(&mut display.ceiling, self.ceiling);
+        override_with(&mut display.peaks, self.peaks);
+        override_with(&mut display.peak_threshold, self.peak_threshold);
+        override_with(&mut display.peak_trace, self.peak_trace);
+        override_with(&mut display.tuner, self.tuner.then_some(true));
+        override_with(&mut display.a4, self.a4);
+        override_with(&mut display.keyboard, self.keyboard.then_some(true));
+        override_with(&mut display.keyboard_threshold, self.keyboard_threshold);
+        override_with(&mut display.view, self.view);
+        override_with(&mut display.colormap, self.colormap);
+    }
+}
+
+fn override_with<T>(setting: &mut T, value: Option<T>) {
+    if let Some(value) = value {
+        *setting = value;
+    }
+}
+
+// Read our options from the command line, exiting with usage information if they don't make sense
+// Settings are only checked against each other once they're combined with the config file, see `Config::validate`
+pub fn parse_args() -> Options {
+    let options = Options::parse();
+
+    // Files end by themselves, but other inputs would run forever without a window to close
+    if options.headless.is_some() && options.duration.is_none() && options.file.is_none() {
+        let mut command = Options::command();
+        command
+            .error(ErrorKind::MissingRequiredArgument, "--headless needs a --duration unless the input is a file")
+            .exit();
+    }
+
+    options
+}
+
+fn parse_fft_size(s: &str) -> Result<usize, String> {
+    match s.parse::<usize>() {
+        Ok(size) if (MIN_FFT_SIZE..=MAX_FFT_SIZE).contains(&size) && size.is_power_of_two() => Ok(size),
+        _ => Err(format!("\"{}\" isn't a power of 2 from {} to {}", s, MIN_FFT_SIZE, MAX_FFT_SIZE)),
+    }
+}
+
+fn parse_number(s: &str) -> Result<f32, String> {
+    match s.parse::<f32>() {
+        Ok(number) if number.is_finite() => Ok(number),
+        _ => Err(format!("\"{}\" isn't a number", s)),
+    }
+}
+
+fn parse_time(s: &str) -> Result<f32, String> {
+    match s.parse::<f32>() {
+        Ok(time) if time >= 0.0 && time.is_finite() => Ok(time),
+        _ => Err(format!("\"{}\" isn't a number of seconds", s)),
+    }
+}
+
+fn parse_duration(s: &str) -> Result<f32, String> {
+    match s.parse::<f32>() {
+        Ok(duration) if duration > 0.0 && duration.is_finite() => Ok(duration),
+        _ => Err(format!("\"{}\" isn't a positive number of seconds", s)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &[&str]) -> Result<Options, clap::Error> {
+        Options::try_parse_from(["live_spectrum"].iter().chain(args))
+    }
+
+    // Settings that aren't all defaults, like they'd come out of a config file
+    fn file_config() -> Config {
+        let mut config = Config::default();
+        config.analysis.fft_size = 2048;
+        config.display.floor = -90.0;
+        config.display.min_freq = Some(50.0);
+        config.display.a4 = 442.0;
+        config.display.tuner = true;
+        config
+    }
+
+    #[test]
+    fn options_override_the_config() {
+        let options = parse(&[
+            "--fft-size", "8192", "--window", "blackman", "--traces", "raw,max-hold", "--floor", "-120",
+            "--min-freq", "100", "--a4", "432", "--axis", "note", "--keyboard",
+        ]).unwrap();
+        let mut config = file_config();
+        options.apply(&mut config);
+        assert_eq!(config.analysis.fft_size, 8192);
+        assert_eq!(config.analysis.window, WindowFunction::Blackman);
+        assert_eq!(config.display.traces, "raw,max-hold".parse().unwrap());
+        assert_eq!((config.display.floor, config.display.min_freq), (-120.0, Some(100.0)));
+        assert_eq!((config.display.a4, config.display.axis), (432.0, FrequencyScale::Note));
+        assert!(config.display.keyboard);
+    }
+
+    #[test]
+    fn missing_options_keep_the_config() {
+        let mut config = file_config();
+        parse(&[]).unwrap().apply(&mut config);
+        assert_eq!(config, file_config());
+
+        // Flags can only turn things on, so leaving one out doesn't turn it off
+        let mut config = file_config();
+        parse(&["--tuner"]).unwrap().apply(&mut config);
+        assert!(config.display.tuner && !config.display.keyboard);
+    }
+
+    #[test]
+    fn bad_values_are_rejected() {
+        for size in ["0", "1", "4", "128", "65536", "1048576", "-1024", "1000", "4096.0", "big"] {
+            assert!(parse_fft_size(size).is_err(), "FFT size {}", size);
+        }
+        assert_eq!(parse_fft_size("1024"), Ok(1024));
+        // As far as the keys go
+        assert_eq!(parse_fft_size("256"), Ok(MIN_FFT_SIZE));
+        assert_eq!(parse_fft_size("32768"), Ok(MAX_FFT_SIZE));
+
+        for duration in ["0", "-1", "NaN", "inf", "soon"] {
+            assert!(parse_duration(duration).is_err(), "duration {}", duration);
+        }
+        assert_eq!(parse_duration("2.5"), Ok(2.5));
+
+        // An attack or release of 0 is fine, it's instant
+        for time in ["-0.1", "NaN", "inf", "slow"] {
+            assert!(parse_time(time).is_err(), "time {}", time);
+        }
+        assert_eq!(parse_time("0"), Ok(0.0));
+
+        for number in ["NaN", "inf", "-inf", "loud"] {
+            assert!(parse_number(number).is_err(), "number {}", number);
+        }
+        assert_eq!(parse_number("-12.5"), Ok(-12.5));
+
+        assert!(parse(&["--fft-size", "1000"]).is_err());
+        assert!(parse(&["--average-frames", "0"]).is_err());
+        assert!(parse(&["--headless", "out.csv", "--duration", "0"]).is_err());
+        assert!(parse(&["--attack", "-1"]).is_err());
+        for option in ["--floor", "--ceiling", "--min-freq", "--max-freq", "--peak-threshold", "--keyboard-threshold", "--a4"] {
+            for value in ["NaN", "inf", "-inf"] {
+                assert!(parse(&[option, value]).is_err(), "{} {}", option, value);
+            }
+        }
+        for option in ["--attack", "--release", "--average-time"] {
+            assert!(parse(&[option, "inf"]).is_err(), "{} inf", option);
+        }
+    }
+}
@@ -0,0 +1,614 @@
+use bevy::prelude::*;
+use live_spectrum::{
+    bin_to_freq, AnalysisWorker, Averaging, Ballistics, ChannelMode, FrequencyAxis, FrequencyScale, LevelAxis, TraceKind, TraceSet,
+};
+use serde::{Deserialize, Deserializer};
+use std::fmt::Display;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::time::SystemTime;
+
+use crate::cli::Options;
+use crate::keyboard::Keyboard;
+use crate::peak_labels::{PeakLabels, MAX_PEAKS};
+use crate::tuner::Tuner;
+use crate::waterfall::{ColorMap, ViewMode};
+use crate::{
+    frequency_axis, AnalysisSettings, FrequencyRange, DEFAULT_ATTACK, DEFAULT_AVERAGE_FRAMES, DEFAULT_AVERAGE_TIME,
+    DEFAULT_DB_CEILING, DEFAULT_DB_FLOOR, DEFAULT_RELEASE, MAX_ENVELOPE_TIME, MAX_FFT_SIZE, MIN_FFT_SIZE,
+};
+
+// How often to check whether the config file changed, in seconds
+const CONFIG_POLL_INTERVAL: f32 = 1.0;
+
+// Everything that can be kept in a config file, see config.example.toml
+// Settings missing from the file keep their defaults, and the command line overrides the file
+#[derive(Clone, Debug, Default, Deserialize, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub analysis: AnalysisSettings,
+    pub display: DisplaySettings,
+    pub appearance: Appearance,
+}
+
+// What the plot shows
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct DisplaySettings {
+    // Which traces are drawn (and written in headless mode, besides the raw one)
+    #[serde(deserialize_with = "from_str")]
+    pub traces: TraceSet,
+    // Envelope time constants in seconds, see `Ballistics`
+    pub envelope_attack: f32,
+    pub envelope_release: f32,
+    // See `Averaging`
+    pub average_time: f32,
+    pub average_frames: usize,
+    // See `PeakLabels`
+    pub peaks: usize,
+    pub peak_threshold: f32,
+    #[serde(deserialize_with = "from_str")]
+    pub peak_trace: TraceKind,
+    // See `Tuner`
+    pub tuner: bool,
+    pub a4: f32,
+    // See `Keyboard`
+    pub keyboard: bool,
+    pub keyboard_threshold: f32,
+    #[serde(deserialize_with = "from_str")]
+    pub axis: FrequencyScale,
+    pub min_freq: Option<f32>,
+    pub max_freq: Option<f32>,
+    pub floor: f32,
+    pub ceiling: f32,
+    #[serde(deserialize_with = "from_str")]
+    pub view: ViewMode,
+    #[serde(deserialize_with = "from_str")]
+    pub colormap: ColorMap,
+}
+
+impl Default for DisplaySettings {
+    fn default() -> Self {
+        DisplaySettings {
+            traces: [TraceKind::Envelope].into_iter().collect(),
+            envelope_attack: DEFAULT_ATTACK,
+            envelope_release: DEFAULT_RELEASE,
+            average_time: DEFAULT_AVERAGE_TIME,
+            average_frames: DEFAULT_AVERAGE_FRAMES,
+            peaks: 5,
+            peak_threshold: -80.0,
+            peak_trace: TraceKind::Envelope,
+            tuner: false,
+            a4: 440.0,
+            keyboard: false,
+            keyboard_threshold: -50.0,
+            axis: FrequencyScale::Linear,
+            min_freq: None,
+            max_freq: None,
+            floor: DEFAULT_DB_FLOOR,
+            ceiling: DEFAULT_DB_CEILING,
+            view: ViewMode::Line,
+            colormap: ColorMap::Viridis,
+        }
+    }
+}
+
+impl DisplaySettings {
+    pub fn ballistics(&self) -> Ballistics {
+        Ballistics {
+            attack: self.envelope_attack,
+            release: self.envelope_release,
+        }
+    }
+
+    pub fn averaging(&self) -> Averaging {
+        Averaging {
+            time: self.average_time,
+            frames: self.average_frames,
+        }
+    }
+
+    pub fn peak_labels(&self) -> PeakLabels {
+        PeakLabels {
+            count: self.peaks,
+            threshold: self.peak_threshold,
+            trace: self.peak_trace,
+            shown: true,
+        }
+    }
+
+    pub fn tuner(&self) -> Tuner {
+        Tuner { a4: self.a4, shown: self.tuner }
+    }
+
+    pub fn keyboard(&self) -> Keyboard {
+        Keyboard { threshold: self.keyboard_threshold, shown: self.keyboard }
+    }
+
+    pub fn levels(&self) -> LevelAxis {
+        LevelAxis::new(self.floor, self.ceiling)
+    }
+
+    pub fn frequency_range(&self) -> FrequencyRange {
+        FrequencyRange {
+            min: self.min_freq,
+            max: self.max_freq,
+        }
+    }
+}
+
+// Where the plot goes and what it looks like. Positions are in pixels from the middle of the window
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct Appearance {
+    pub plot_width: f32,
+    pub plot_height: f32,
+    // Where the bottom of the plot goes
+    pub plot_y_zero: f32,
+    #[serde(deserialize_with = "color")]
+    pub background: Color,
+    #[serde(deserialize_with = "color")]
+    pub line_color: Color,
+    #[serde(deserialize_with = "color")]
+    pub scale_color: Color,
+    #[serde(deserialize_with = "color")]
+    pub grid_color: Color,
+    // For input errors
+    #[serde(deserialize_with = "color")]
+    pub error_color: Color,
+    // For the markers pinned to the plot
+    #[serde(deserialize_with = "color")]
+    pub marker_color: Color,
+    // For each channel when they're analysed separately, going round again if there are more channels
+    #[serde(deserialize_with = "colors")]
+    pub channel_colors: Vec<Color>,
+    // For traces other than the envelope, which uses the line color. Separately analysed channels
+    // use their own colors for every trace
+    pub trace_colors: TraceColors,
+    // Relative to the assets folder
+    pub font: String,
+}
+
+impl Default for Appearance {
+    fn default() -> Self {
+        Appearance {
+            plot_width: 800.0,
+            plot_height: 360.0,
+            plot_y_zero: -50.0,
+            background: Color::WHITE,
+            line_color: Color::BLACK,
+            scale_color: Color::GRAY,
+            grid_color: Color::rgb(0.9, 0.9, 0.9),
+            error_color: Color::rgb(0.8, 0.1, 0.1),
+            marker_color: Color::hex("ff7f0e").unwrap(),
+            channel_colors: ["1f77b4", "d62728", "2ca02c", "ff7f0e", "9467bd", "8c564b"]
+                .iter()
+                .map(|hex| Color::hex(hex).unwrap())
+                .collect(),
+            trace_colors: TraceColors::default(),
+            font: "fonts/EBGaramond-Medium.ttf".to_string(),
+        }
+    }
+}
+
+impl Appearance {
+    // The color to draw trace `kind` of signal `signal` out of `signals` in
+    // A lone signal's traces each have their own color, otherwise every channel has one and its
+    // traces other than the envelope are drawn fainter
+    pub fn trace_color(&self, kind: TraceKind, signal: usize, signals: usize) -> Color {
+        if signals == 1 {
+            let colors = &self.trace_colors;
+            return match kind {
+                TraceKind::Raw => colors.raw,
+                TraceKind::Envelope => self.line_color,
+                TraceKind::Exponential => colors.exponential,
+                TraceKind::Linear => colors.linear,
+                TraceKind::MaxHold => colors.max_hold,
+                TraceKind::MinHold => colors.min_hold,
+            };
+        }
+
+        let mut color = self.channel_colors[signal % self.channel_colors.len()];
+        if kind != TraceKind::Envelope {
+            color.set_a(0.5);
+        }
+        color
+    }
+}
+
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+#[serde(default, deny_unknown_fields, rename_all = "kebab-case")]
+pub struct TraceColors {
+    #[serde(deserialize_with = "color")]
+    pub raw: Color,
+    #[serde(deserialize_with = "color")]
+    pub exponential: Color,
+    #[serde(deserialize_with = "color")]
+    pub linear: Color,
+    #[serde(deserialize_with = "color")]
+    pub max_hold: Color,
+    #[serde(deserialize_with = "color")]
+    pub min_hold: Color,
+}
+
+impl Default for TraceColors {
+    fn default() -> Self {
+        TraceColors {
+            raw: Color::rgb(0.7, 0.7, 0.7),
+            exponential: Color::hex("2ca02c").unwrap(),
+            linear: Color::hex("9467bd").unwrap(),
+            max_hold: Color::hex("d62728").unwrap(),
+            min_hold: Color::hex("1f77b4").unwrap(),
+        }
+    }
+}
+
+impl Config {
+    // Check the settings make sense together, and for an input running at `sample_rate` with `channels` channels
+    pub fn validate(&self, sample_rate: u32, channels: u16) -> Result<(), String> {
+        let AnalysisSettings { fft_size, step_size, channels: mode, .. } = self.analysis;
+        if !(MIN_FFT_SIZE..=MAX_FFT_SIZE).contains(&fft_size) || !fft_size.is_power_of_two() {
+            return Err(format!(
+                "The FFT size ({}) must be a power of 2 from {} to {}",
+                fft_size, MIN_FFT_SIZE, MAX_FFT_SIZE
+            ));
+        }
+        if step_size == 0 || step_size > fft_size {
+            return Err(format!("The hop ({}) must be between 1 and the FFT size ({})", step_size, fft_size));
+        }
+        if let ChannelMode::Single(channel) = mode {
+            if channel >= channels as usize {
+                return Err(format!("There's no channel {}, the input only has {}", channel + 1, channels));
+            }
+        }
+
+        let display = &self.display;
+        let numbers = [
+            ("envelope_attack", Some(display.envelope_attack)),
+            ("envelope_release", Some(display.envelope_release)),
+            ("average_time", Some(display.average_time)),
+            ("peak_threshold", Some(display.peak_threshold)),
+            ("keyboard_threshold", Some(display.keyboard_threshold)),
+            ("a4", Some(display.a4)),
+            ("min_freq", display.min_freq),
+            ("max_freq", display.max_freq),
+            ("floor", Some(display.floor)),
+            ("ceiling", Some(display.ceiling)),
+        ];
+        // NaN gets past every comparison below, so it has to be caught first
+        let not_finite = numbers.into_iter().find(|(_, number)| number.is_some_and(|number| !number.is_finite()));
+        if let Some((name, Some(number))) = not_finite {
+            return Err(format!("The {} setting must be a number, not {}", name, number));
+        }
+        for (name, time) in [("attack", display.envelope_attack), ("release", display.envelope_release)] {
+            if !(0.0..=MAX_ENVELOPE_TIME).contains(&time) {
+                return Err(format!(
+                    "The envelope {} ({} s) must be between 0 and {} seconds",
+                    name, time, MAX_ENVELOPE_TIME
+                ));
+            }
+        }
+        if display.average_time < 0.0 || !display.average_time.is_finite() {
+            return Err(format!("The average time ({} s) can't be negative", display.average_time));
+        }
+        if display.average_frames == 0 {
+            return Err("The average must cover at least 1 frame".to_string());
+        }
+        if display.peaks > MAX_PEAKS {
+            return Err(format!("At most {} peaks can be labelled, not {}", MAX_PEAKS, display.peaks));
+        }
+        if !(display.a4 > 0.0 && display.a4.is_finite()) {
+            return Err(format!("A4 has to be above 0 Hz, not {}", display.a4));
+        }
+        if display.floor >= display.ceiling {
+            return Err(format!(
+                "The floor ({} dB) must be below the ceiling ({} dB)",
+                display.floor, display.ceiling
+            ));
+        }
+        if display.min_freq.unwrap_or(0.0) < 0.0 {
+            return Err("The lowest frequency can't be negative".to_string());
+        }
+        if let (Some(min), Some(max)) = (display.min_freq, display.max_freq) {
+            if min >= max {
+                return Err(format!("The lowest frequency ({} Hz) must be below the highest ({} Hz)", min, max));
+            }
+        }
+        let nyquist = sample_rate as f32 / 2.0;
+        if let Some(freq) = display.min_freq.into_iter().chain(display.max_freq).find(|&freq| freq > nyquist) {
+            return Err(format!("{} Hz is above the highest frequency the input has ({} Hz)", freq, nyquist));
+        }
+        // Past the top bin there'd be nothing left to show
+        let top_bin = bin_to_freq((fft_size / 2 - 1) as f32, fft_size, sample_rate);
+        if let Some(min) = display.min_freq.filter(|&min| min >= top_bin) {
+            return Err(format!(
+                "The lowest frequency ({} Hz) must be below the top bin of the FFT ({} Hz)",
+                min, top_bin
+            ));
+        }
+
+        let appearance = &self.appearance;
+        if appearance.plot_width <= 0.0 || appearance.plot_height <= 0.0 {
+            return Err("The plot must be wider and taller than 0".to_string());
+        }
+        if appearance.channel_colors.is_empty() {
+            return Err("There must be at least one channel color".to_string());
+        }
+        Ok(())
+    }
+}
+
+// Where the config file is looked for unless one is picked on the command line
+pub fn default_path() -> Option<PathBuf> {
+    dirs::config_dir().map(|dir| dir.join("live_spectrum").join("config.toml"))
+}
+
+pub fn load(path: &Path) -> Result<Config, String> {
+    let text = std::fs::read_to_string(path)
+        .map_err(|err| format!("Couldn't read config file {}: {}", path.display(), err))?;
+    toml::from_str(&text).map_err(|err| format!("Invalid config file {}: {}", path.display(), err))
+}
+
+// For settings written the same way as on the command line
+pub fn from_str<'de, D, T>(deserializer: D) -> Result<T, D::Error>
+where
+    D: Deserializer<'de>,
+    T: FromStr,
+    T::Err: Display,
+{
+    let s = String::deserialize(deserializer)?;
+    s.parse().map_err(serde::de::Error::custom)
+}
+
+// Colors are written in hex, like "#1f77b4"
+fn color<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Color, D::Error> {
+    let s = String::deserialize(deserializer)?;
+    parse_color(&s).map_err(serde::de::Error::custom)
+}
+
+fn colors<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<Color>, D::Error> {
+    let colors = Vec::<String>::deserialize(deserializer)?;
+    colors.iter().map(|s| parse_color(s).map_err(serde::de::Error::custom)).collect()
+}
+
+fn parse_color(s: &str) -> Result<Color, String> {
+    Color::hex(s.trim_start_matches('#')).map_err(|_| format!("invalid color \"{}\", expected one like \"#1f77b4\"", s))
+}
+
+// Keeps an eye on the config file so changes to it show up without restarting
+pub struct ConfigWatcher {
+    path: PathBuf,
+    modified: Option<SystemTime>,
+    timer: Timer,
+    // The command line, which still overrides the file
+    options: Options,
+    // The settings last applied, so only what changed in the file replaces whatever's been
+    // switched with the keys since
+    current: Config,
+}
+
+impl ConfigWatcher {
+    pub fn new(path: PathBuf, options: Options, current: Config) -> Self {
+        ConfigWatcher {
+            modified: modified(&path),
+            path,
+            timer: Timer::from_seconds(CONFIG_POLL_INTERVAL, true),
+            options,
+            current,
+        }
+    }
+
+    // The settings in the file, with the command line still overriding them, if they're any good
+    // for an input at `sample_rate` with `channels` channels
+    fn read(&self, sample_rate: u32, channels: u16) -> Result<Config, String> {
+        let mut config = load(&self.path)?;
+        self.options.apply(&mut config);
+        config.validate(sample_rate, channels)?;
+        Ok(config)
+    }
+}
+
+fn modified(path: &Path) -> Option<SystemTime> {
+    std::fs::metadata(path).and_then(|metadata| metadata.modified()).ok()
+}
+
+// Re-read the config file whenever it changes and apply whatever's different
+// A broken file is reported and otherwise ignored, so it can be fixed while we keep running
+#[allow(clippy::too_many_arguments)]
+pub fn reload_config(
+    time: Res<Time>,
+    mut watcher: ResMut<ConfigWatcher>,
+    analyzer: Res<AnalysisWorker>,
+    mut analysis: ResMut<AnalysisSettings>,
+    mut traces: ResMut<TraceSet>,
+    mut ballistics: ResMut<Ballistics>,
+    mut averaging: ResMut<Averaging>,
+    mut peaks: ResMut<PeakLabels>,
+    mut tuner: ResMut<Tuner>,
+    mut keyboard: ResMut<Keyboard>,
+    mut levels: ResMut<LevelAxis>,
+    mut range: ResMut<FrequencyRange>,
+    mut axis: ResMut<FrequencyAxis>,
+    mut view: ResMut<ViewMode>,
+    mut colormap: ResMut<ColorMap>,
+    mut appearance: ResMut<Appearance>,
+) {
+    if !watcher.timer.tick(time.delta()).just_finished() {
+        return;
+    }
+    // Leave things be if the file has gone, it's probably being replaced
+    let modified = modified(&watcher.path);
+    if modified.is_none() || modified == watcher.modified {
+        return;
+    }
+    watcher.modified = modified;
+
+    let config = match watcher.read(analyzer.sample_rate(), analyzer.channels() as u16) {
+        Ok(config) => config,
+        Err(err) => {
+            warn!("{}, keeping the current settings", err);
+            return;
+        }
+    };
+    info!("Reloaded {}", watcher.path.display());
+
+    let old = std::mem::replace(&mut watcher.current, config.clone());
+    let (new_display, old_display) = (config.display, old.display);
+
+    if config.analysis != old.analysis {
+        *analysis = config.analysis;
+    }
+    if new_display.traces != old_display.traces {
+        *traces = new_display.traces;
+    }
+    if new_display.ballistics() != old_display.ballistics() {
+        *ballistics = new_display.ballistics();
+    }
+    if new_display.averaging() != old_display.averaging() {
+        *averaging = new_display.averaging();
+    }
+    if new_display.peak_labels() != old_display.peak_labels() {
+        *peaks = new_display.peak_labels();
+    }
+    if new_display.tuner() != old_display.tuner() {
+        *tuner = new_display.tuner();
+    }
+    if new_display.keyboard() != old_display.keyboard() {
+        *keyboard = new_display.keyboard();
+    }
+    if new_display.levels() != old_display.levels() {
+        *levels = new_display.levels();
+    }
+    if new_display.axis != old_display.axis || new_display.frequency_range() != old_display.frequency_range() {
+        // Stay on whichever axis L picked, unless that's what changed
+        let scale = if new_display.axis != old_display.axis { new_display.axis } else { axis.scale };
+        *range = new_display.frequency_range();
+        *axis = frequency_axis(scale, &range, analyzer.sample_rate());
+    }
+    if new_display.view != old_display.view {
+        *view = new_display.view;
+    }
+    if new_display.colormap != old_display.colormap {
+        *colormap = new_display.colormap;
+    }
+    if config.appearance != old.appearance {
+        *appearance = config.appearance;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::Parser;
+
+    // A config file holding `text`, named after the test so tests running at once keep apart
+    struct TempFile(PathBuf);
+
+    impl TempFile {
+        fn new(name: &str, text: &str) -> Self {
+            let path = std::env::temp_dir().join(format!("live_spectrum_{}_{}.toml", name, std::process::id()));
+            std::fs::write(&path, text).unwrap();
+            TempFile(path)
+        }
+    }
+
+    impl Drop for TempFile {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_file(&self.0);
+        }
+    }
+
+    fn load_text(name: &str, text: &str) -> Result<Config, String> {
+        load(&TempFile::new(name, text).0)
+    }
+
+    #[test]
+    fn missing_settings_keep_their_defaults() {
+        let config = load_text("partial", "[display]\nfloor = -90\n\n[appearance]\nline_color = \"#ff0000\"\n").unwrap();
+        let defaults = Config::default();
+        assert_eq!(config.display.floor, -90.0);
+        assert_eq!(config.display.ceiling, defaults.display.ceiling);
+        assert_eq!(config.appearance.line_color, Color::rgb(1.0, 0.0, 0.0));
+        assert_eq!(config.appearance.background, defaults.appearance.background);
+        assert_eq!(config.analysis, defaults.analysis);
+
+        // And an empty file is all defaults
+        assert_eq!(load_text("empty", "").unwrap(), defaults);
+        assert!(config.validate(48000, 2).is_ok());
+        assert!(load("/nowhere/config.toml".as_ref()).unwrap_err().starts_with("Couldn't read config file"));
+    }
+
+    #[test]
+    fn unknown_keys_and_bad_values_are_rejected() {
+        for (name, text, message) in [
+            ("unknown_key", "[display]\nflor = -90\n", "unknown field `flor`"),
+            ("unknown_table", "[displays]\nfloor = -90\n", "unknown field `displays`"),
+            ("bad_window", "[analysis]\nwindow = \"triangle\"\n", "Unknown window \"triangle\""),
+            ("bad_type", "[analysis]\nfft_size = \"big\"\n", "fft_size"),
+            ("bad_traces", "[display]\ntraces = \"envelope,peak\"\n", "Unknown trace \"peak\""),
+            ("bad_color", "[appearance]\nbackground = \"#12345\"\n", "invalid color \"#12345\""),
+        ] {
+            let err = load_text(name, text).unwrap_err();
+            assert!(err.starts_with("Invalid config file") && err.contains(message), "{}: {}", name, err);
+        }
+    }
+
+    #[test]
+    fn inconsistent_settings_fail_validation() {
+        let check = |change: fn(&mut Config), message: &str| {
+            let mut config = Config::default();
+            change(&mut config);
+            let err = config.validate(48000, 2).unwrap_err();
+            assert!(err.contains(message), "{}", err);
+        };
+        check(|config| config.analysis.step_size = config.analysis.fft_size * 2, "must be between 1 and the FFT size");
+        check(|config| (config.analysis.fft_size, config.analysis.step_size) = (4, 1), "power of 2 from 256 to 32768");
+        check(|config| config.analysis.fft_size = 1 << 20, "power of 2 from 256 to 32768");
+        check(|config| config.analysis.fft_size = 3000, "power of 2 from 256 to 32768");
+        check(|config| config.display.floor = config.display.ceiling, "must be below the ceiling");
+        check(|config| (config.display.min_freq, config.display.max_freq) = (Some(1000.0), Some(1000.0)), "must be below the highest");
+        check(|config| config.display.max_freq = Some(30000.0), "above the highest frequency the input has");
+        check(|config| config.display.floor = f32::NEG_INFINITY, "floor setting must be a number");
+        check(|config| config.display.min_freq = Some(f32::NAN), "min_freq setting must be a number");
+        // Between the top bin (23988.3 Hz) and Nyquist
+        check(|config| config.display.min_freq = Some(23995.0), "must be below the top bin");
+        check(|config| config.display.min_freq = Some(24000.0), "must be below the top bin");
+        assert!(Config::default().validate(48000, 2).is_ok());
+    }
+
+    #[test]
+    fn non_finite_numbers_in_the_file_are_rejected() {
+        for key in ["floor", "ceiling", "min_freq", "max_freq", "peak_threshold", "keyboard_threshold", "a4",
+                    "envelope_attack", "envelope_release", "average_time"] {
+            for value in ["nan", "inf", "-inf"] {
+                let config = load_text(key, &format!("[display]\n{} = {}\n", key, value)).unwrap();
+                let err = config.validate(48000, 2).unwrap_err();
+                assert!(err.contains(&format!("The {} setting must be a number", key)), "{} = {}: {}", key, value, err);
+            }
+        }
+    }
+
+    #[test]
+    fn colors_are_hex() {
+        assert_eq!(parse_color("#1f77b4"), Ok(Color::rgb_u8(0x1f, 0x77, 0xb4)));
+        assert_eq!(parse_color("1f77b4"), Ok(Color::rgb_u8(0x1f, 0x77, 0xb4)));
+        assert_eq!(parse_color("#1f77b480"), Ok(Color::rgba_u8(0x1f, 0x77, 0xb4, 0x80)));
+        assert_eq!(parse_color("#fff"), Ok(Color::WHITE));
+        for bad in ["", "#", "#12345", "#1f77b4a", "#gggggg", "red"] {
+            assert!(parse_color(bad).is_err(), "\"{}\" parsed", bad);
+        }
+    }
+
+    #[test]
+    fn reloads_keep_the_command_line() {
+        let file = TempFile::new("reload", "[display]\nfloor = -90\nceiling = -10\n");
+        let options = Options::try_parse_from(["live_spectrum", "--ceiling", "0"]).unwrap();
+        let watcher = ConfigWatcher::new(file.0.clone(), options, Config::default());
+        let config = watcher.read(48000, 2).unwrap();
+        assert_eq!((config.display.floor, config.display.ceiling), (-90.0, 0.0));
+
+        // A file that's bad for this input doesn't get through
+        std::fs::write(&file.0, "[analysis]\nchannels = \"3\"\n").unwrap();
+        assert!(watcher.read(48000, 2).unwrap_err().contains("There's no channel 3"));
+    }
+}
@@ -0,0 +1,260 @@
+use bevy::ecs::system::SystemParam;
+use bevy::prelude::*;
+use bevy_prototype_lyon::prelude::*;
+use live_spectrum::{level_at, AnalysisWorker, FrequencyAxis, LevelAxis, TraceSet};
+
+use crate::config::Appearance;
+use crate::waterfall::ViewMode;
+use crate::{Label, Spectrum, SpectrumLine, OVERLAY_Z};
+
+// How many markers can be pinned at once, a new one past that replaces the last
+const MAX_MARKERS: usize = 2;
+
+// The crosshair following the mouse over the line plot
+#[derive(Component)]
+pub struct Crosshair;
+// Its readout of the frequency and levels under it
+#[derive(Component)]
+pub struct CursorLabel;
+// Lines at the pinned frequencies
+#[derive(Component)]
+pub struct MarkerLines;
+// The letter at the top of the marker line at this index
+#[derive(Component)]
+pub struct MarkerTag(usize);
+// The readouts at the markers, and the difference between them
+#[derive(Component)]
+pub struct MarkerLabel;
+
+// The filters that keep the text labels' queries apart
+type AnyLabel = Or<(With<CursorLabel>, With<MarkerLabel>, With<MarkerTag>)>;
+type MarkerLabelFilter = (With<MarkerLabel>, Without<MarkerTag>, Without<MarkerLines>);
+
+// Frequencies pinned with a right click, in Hz, in the order they were pinned
+#[derive(Default)]
+pub struct Markers(Vec<f32>);
+
+pub fn setup_cursor(mut commands: Commands) {
+    // Colored in by `style_cursor`
+    let lines = || GeometryBuilder::build_as(
+        &PathBuilder::new().build(),
+        DrawMode::Stroke(StrokeMode::new(Color::NONE, 1.0)),
+        Transform::from_xyz(0.0, 0.0, OVERLAY_Z),
+    );
+    commands.spawn_bundle(lines()).insert(Crosshair);
+    commands.spawn_bundle(lines()).insert(MarkerLines);
+
+    // Filled in and placed as the mouse moves and markers are pinned
+    let label = |value: String, horizontal, vertical| Text2dBundle {
+        text: Text::with_section(value, TextStyle::default(), TextAlignment { vertical, horizontal }),
+        ..default()
+    };
+    commands.spawn_bundle(label(String::new(), HorizontalAlign::Left, VerticalAlign::Bottom)).insert(CursorLabel);
+    commands.spawn_bundle(label(String::new(), HorizontalAlign::Left, VerticalAlign::Top)).insert(MarkerLabel);
+    for index in 0..MAX_MARKERS {
+        commands.spawn_bundle(label(marker_name(index), HorizontalAlign::Center, VerticalAlign::Bottom))
+            .insert(MarkerTag(index));
+    }
+}
+
+// Restyle the crosshair, markers and their labels whenever the appearance changes
+pub fn style_cursor(
+    appearance: Res<Appearance>,
+    asset_server: Res<AssetServer>,
+    mut crosshair: Query<&mut DrawMode, With<Crosshair>>,
+    mut marker_lines: Query<&mut DrawMode, (With<MarkerLines>, Without<Crosshair>)>,
+    mut labels: Query<&mut Text, AnyLabel>
+) {
+    if !appearance.is_changed() {
+        return;
+    }
+
+    *crosshair.single_mut() = DrawMode::Stroke(StrokeMode::new(appearance.scale_color, 1.0));
+    *marker_lines.single_mut() = DrawMode::Stroke(StrokeMode::new(appearance.marker_color, 1.0));
+    for mut text in labels.iter_mut() {
+        text.sections[0].style = TextStyle {
+            font: asset_server.load(&appearance.font),
+            font_size: 12.0,
+            color: appearance.scale_color,
+        };
+    }
+}
+
+// Follow the mouse over the line plot with the crosshair, reading out the frequency and level
+// under it and the level of every trace at that frequency
+#[allow(clippy::too_many_arguments)]
+pub fn update_cursor(
+    windows: Res<Windows>,
+    view: Res<ViewMode>,
+    axis: Res<FrequencyAxis>,
+    levels: Res<LevelAxis>,
+    appearance: Res<Appearance>,
+    readouts: Readouts,
+    mut crosshair: Query<(&mut Path, &mut Visibility), With<Crosshair>>,
+    mut label: Query<Label, (With<CursorLabel>, Without<Crosshair>)>
+) {
+    let (mut path, mut crosshair_visibility) = crosshair.single_mut();
+    let (mut text, mut transform, mut label_visibility) = label.single_mut();
+    let cursor = plot_cursor(&windows, &appearance).filter(|_| *view != ViewMode::Waterfall);
+    crosshair_visibility.is_visible = cursor.is_some();
+    label_visibility.is_visible = cursor.is_some();
+    let cursor = match cursor {
+        Some(cursor) => cursor,
+        None => return,
+    };
+
+    let (left, right) = (-appearance.plot_width / 2.0, appearance.plot_width / 2.0);
+    let (bottom, top) = (appearance.plot_y_zero, appearance.plot_y_zero + appearance.plot_height);
+    let mut path_builder = PathBuilder::new();
+    path_builder.move_to(Vec2::new(cursor.x, bottom));
+    path_builder.line_to(Vec2::new(cursor.x, top));
+    path_builder.move_to(Vec2::new(left, cursor.y));
+    path_builder.line_to(Vec2::new(right, cursor.y));
+    *path = path_builder.build();
+
+    let freq = axis.freq_at(cursor.x / appearance.plot_width + 0.5);
+    let level = levels.level_at((cursor.y - bottom) / appearance.plot_height);
+    let mut readout = format!("{}, {:.1} dB", format_freq(freq), level);
+    for (name, level) in readouts.levels(freq) {
+        readout += &format!("\n{} {:.1} dB", name, level);
+    }
+    text.sections[0].value = readout;
+
+    // Keep the label over the plot, on whichever side of the crosshair has more room
+    let (horizontal, offset) = if cursor.x > 0.0 { (HorizontalAlign::Right, -8.0) } else { (HorizontalAlign::Left, 8.0) };
+    text.alignment.horizontal = horizontal;
+    transform.translation = Vec3::new(cursor.x + offset, cursor.y + 6.0, OVERLAY_Z);
+}
+
+// Pin a marker at the frequency under the mouse with a right click, and clear them all with X
+pub fn pin_markers(
+    buttons: Res<Input<MouseButton>>,
+    keys: Res<Input<KeyCode>>,
+    windows: Res<Windows>,
+    view: Res<ViewMode>,
+    axis: Res<FrequencyAxis>,
+    appearance: Res<Appearance>,
+    mut markers: ResMut<Markers>
+) {
+    if keys.just_pressed(KeyCode::X) && !markers.0.is_empty() {
+        markers.0.clear();
+    }
+    if !buttons.just_pressed(MouseButton::Right) || *view == ViewMode::Waterfall {
+        return;
+    }
+    if let Some(cursor) = plot_cursor(&windows, &appearance) {
+        if markers.0.len() == MAX_MARKERS {
+            markers.0.pop();
+        }
+        markers.0.push(axis.freq_at(cursor.x / appearance.plot_width + 0.5));
+    }
+}
+
+// Draw a line at each marker that's on the axis, and read out the level of every trace at each of
+// them, and how far the second is from the first
+#[allow(clippy::too_many_arguments)]
+pub fn draw_markers(
+    markers: Res<Markers>,
+    view: Res<ViewMode>,
+    axis: Res<FrequencyAxis>,
+    appearance: Res<Appearance>,
+    readouts: Readouts,
+    mut marker_lines: Query<(&mut Path, &mut Visibility), With<MarkerLines>>,
+    mut tags: Query<(&mut Transform, &mut Visibility, &MarkerTag), Without<MarkerLines>>,
+    mut label: Query<Label, MarkerLabelFilter>
+) {
+    let shown = *view != ViewMode::Waterfall;
+    let (bottom, top) = (appearance.plot_y_zero, appearance.plot_y_zero + appearance.plot_height);
+    let x = |freq: f32| Some(axis.position(freq)).filter(|position| (0.0..=1.0).contains(position))
+        .map(|position| (position - 0.5) * appearance.plot_width);
+
+    let (mut path, mut visibility) = marker_lines.single_mut();
+    let mut path_builder = PathBuilder::new();
+    for x in markers.0.iter().filter_map(|&freq| x(freq)) {
+        path_builder.move_to(Vec2::new(x, bottom));
+        path_builder.line_to(Vec2::new(x, top));
+    }
+    *path = path_builder.build();
+    visibility.is_visible = shown;
+
+    for (mut transform, mut visibility, tag) in tags.iter_mut() {
+        let x = markers.0.get(tag.0).and_then(|&freq| x(freq));
+        visibility.is_visible = shown && x.is_some();
+        transform.translation = Vec3::new(x.unwrap_or(0.0), top + 2.0, OVERLAY_Z);
+    }
+
+    let (mut text, mut transform, mut visibility) = label.single_mut();
+    visibility.is_visible = shown && !markers.0.is_empty();
+    transform.translation = Vec3::new(-appearance.plot_width / 2.0 + 10.0, top - 10.0, OVERLAY_Z);
+    if !visibility.is_visible {
+        return;
+    }
+
+    let marker_levels: Vec<_> = markers.0.iter().map(|&freq| readouts.levels(freq)).collect();
+    let mut lines: Vec<_> = markers.0.iter().zip(&marker_levels).enumerate()
+        .map(|(index, (&freq, levels))| format!("{} {}{}", marker_name(index), format_freq(freq), list_levels(levels, false)))
+        .collect();
+    if let ([first, second], [first_levels, second_levels]) = (&markers.0[..], &marker_levels[..]) {
+        let difference: Vec<_> = first_levels.iter().zip(second_levels)
+            .map(|((name, first), (_, second))| (name.clone(), second - first))
+            .collect();
+        lines.push(format!("B - A {:+.1} Hz{}", second - first, list_levels(&difference, true)));
+    }
+    text.sections[0].value = lines.join("\n");
+}
+
+// The lines on the plot, for reading levels off of
+#[derive(SystemParam)]
+pub(crate) struct Readouts<'w, 's> {
+    traces: Res<'w, TraceSet>,
+    analyzer: Res<'w, AnalysisWorker>,
+    lines: Query<'w, 's, (&'static Spectrum, &'static SpectrumLine)>,
+}
+
+impl<'w, 's> Readouts<'w, 's> {
+    // The name and level at `freq` of every line, by trace and then by signal
+    fn levels(&self, freq: f32) -> Vec<(String, f32)> {
+        let signals = self.analyzer.signals();
+        let mut levels = Vec::new();
+        for kind in self.traces.iter() {
+            for signal in 0..signals {
+                let line = self.lines.iter().find(|(_, line)| line.trace.kind() == kind && line.signal == signal);
+                if let Some((spectrum, line)) = line {
+                    levels.push((line.name(signals), level_at(&spectrum.0, freq, self.analyzer.bin_width())));
+                }
+            }
+        }
+        levels
+    }
+}
+
+// Where the mouse is over the window, in the same coordinates everything's drawn in
+pub fn cursor_position(windows: &Windows) -> Option<Vec2> {
+    let window = windows.get_primary()?;
+    let cursor = window.cursor_position()?;
+    Some(cursor - Vec2::new(window.width(), window.height()) / 2.0)
+}
+
+// Where the mouse is, if it's over the line plot
+fn plot_cursor(windows: &Windows, appearance: &Appearance) -> Option<Vec2> {
+    cursor_position(windows).filter(|cursor| {
+        cursor.x.abs() <= appearance.plot_width / 2.0
+            && (appearance.plot_y_zero..=appearance.plot_y_zero + appearance.plot_height).contains(&cursor.y)
+    })
+}
+
+fn marker_name(index: usize) -> String {
+    ((b'A' + index as u8) as char).to_string()
+}
+
+fn format_freq(freq: f32) -> String {
+    format!("{:.1} Hz", freq)
+}
+
+// ": envelope -12.3 dB, max-hold -10.0 dB", with a + in front of rises if `signed`
+fn list_levels(levels: &[(String, f32)], signed: bool) -> String {
+    let levels: Vec<_> = levels.iter()
+        .map(|(name, level)| if signed { format!("{} {:+.1} dB", name, level) } else { format!("{} {:.1} dB", name, level) })
+        .collect();
+    if levels.is_empty() { String::new() } else { format!(": {}", levels.join(", ")) }
+}
@@ -0,0 +1,63 @@
+// How quickly the envelope follows the spectrum, as time constants in seconds, so it behaves the
+// same however often it's updated
+// It rises towards louder levels with the attack time (0 jumps straight up to them), and falls
+// back towards quieter ones with the release time
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Ballistics {
+    pub attack: f32,
+    pub release: f32,
+}
+
+impl Ballistics {
+    // How much of the old level is kept on each update, for updates `interval` seconds apart
+    fn coefficients(&self, interval: f32) -> (f32, f32) {
+        let coefficient = |time: f32| if time > 0.0 { (-interval / time).exp() } else { 0.0 };
+        (coefficient(self.attack), coefficient(self.release))
+    }
+}
+
+// Peak-hold filter over successive spectrum frames, `interval` seconds apart
+pub fn update_envelope(envelope: &mut [f32], raw: &[f32], ballistics: &Ballistics, interval: f32) {
+    let (attack, release) = ballistics.coefficients(interval);
+    for (envelope, raw) in envelope.iter_mut().zip(raw) {
+        let keep = if raw > envelope { attack } else { release };
+        *envelope = *envelope*keep + raw*(1.0-keep);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn jumps_up_to_peaks() {
+        let ballistics = Ballistics { attack: 0.0, release: 1.0 };
+        let mut envelope = [1.0, 1.0];
+        update_envelope(&mut envelope, &[3.0, 0.5], &ballistics, 0.1);
+        assert_eq!(envelope[0], 3.0);
+        assert!(envelope[1] < 1.0);
+    }
+
+    #[test]
+    fn decays_towards_raw() {
+        let ballistics = Ballistics { attack: 0.0, release: 1.0 };
+        let mut envelope = [2.0];
+        update_envelope(&mut envelope, &[0.0], &ballistics, 1.0);
+        assert!((envelope[0] - 2.0 / std::f32::consts::E).abs() < 1e-6);
+    }
+
+    #[test]
+    fn decay_doesnt_depend_on_the_update_rate() {
+        let ballistics = Ballistics { attack: 0.05, release: 0.3 };
+        let (mut slow, mut fast) = ([0.0, -50.0], [0.0, -50.0]);
+        let raw = [-50.0, 0.0];
+        for _ in 0..10 {
+            update_envelope(&mut slow, &raw, &ballistics, 0.02);
+        }
+        for _ in 0..40 {
+            update_envelope(&mut fast, &raw, &ballistics, 0.005);
+        }
+        assert!((slow[0] - fast[0]).abs() < 1e-3);
+        assert!((slow[1] - fast[1]).abs() < 1e-3);
+    }
+}
@@ -0,0 +1,207 @@
+use std::error::Error;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+use std::thread;
+use std::time::Duration;
+
+use live_spectrum::source::{SampleSource, SourceError};
+use live_spectrum::{Trace, TraceKind, MIN_DB};
+
+use crate::config::DisplaySettings;
+use crate::AnalysisSettings;
+
+// Run the same analysis as the windowed app, but write every spectrum column to a CSV file instead
+// The header row holds the frequency of each bin, then every column gets a row for the raw
+// spectrum and one for each other trace shown, each starting with the time (in seconds) of the
+// column's center and the trace's name
+// When channels are analysed separately every channel gets its own rows, with its number (from 1)
+// after the trace name
+// Only bins inside the display's frequency range are written, all of them unless it says otherwise
+// Runs until the input is finished, or until `duration` seconds of input have been analysed
+pub fn run(
+    mut source: Box<dyn SampleSource>,
+    path: &Path,
+    duration: Option<f32>,
+    settings: &AnalysisSettings,
+    display: &DisplaySettings,
+) -> Result<(), Box<dyn Error>> {
+    let sample_rate = source.sample_rate();
+    let mut analyzer = settings.analyzer(sample_rate, source.channels());
+    let first = analyzer.first();
+    let range = display.frequency_range();
+    let (ballistics, averaging) = (display.ballistics(), display.averaging());
+
+    let first_bin = first.freq_to_bin(range.min.unwrap_or(0.0)).ceil() as usize;
+    if first_bin >= first.bins() {
+        return Err(format!(
+            "The lowest frequency ({} Hz) is above the top bin of the FFT ({} Hz)",
+            range.min.unwrap_or(0.0),
+            first.bin_to_freq((first.bins() - 1) as f32)
+        ).into());
+    }
+    let last_bin = range.max
+        .map(|max| first.freq_to_bin(max).floor() as usize)
+        .unwrap_or(first.bins())
+        .min(first.bins() - 1)
+        .max(first_bin);
+    let bins = first_bin..=last_bin;
+    let interval = first.frame_interval();
+
+    let mut out = BufWriter::new(File::create(path)?);
+
+    write!(out, "time,trace")?;
+    for bin in bins.clone() {
+        write!(out, ",{}", first.bin_to_freq(bin as f32))?;
+    }
+    writeln!(out)?;
+
+    let signals = analyzer.signals();
+    let mut raw = vec![vec![MIN_DB; analyzer.bins()]; signals];
+    let name = |kind: TraceKind, signal: usize| match signals {
+        1 => kind.to_string(),
+        _ => format!("{} {}", kind, signal + 1),
+    };
+    // The raw spectrum is written anyway
+    let kinds: Vec<_> = display.traces.iter().filter(|&kind| kind != TraceKind::Raw).collect();
+    let mut traces: Vec<Vec<_>> = (0..signals)
+        .map(|signal| {
+            kinds.iter()
+                .map(|&kind| (name(kind, signal), Trace::new(kind), vec![MIN_DB; analyzer.bins()]))
+                .collect()
+        })
+        .collect();
+
+    // Samples are interleaved, so that's a frame of every channel for each sample period
+    let channels = source.channels() as usize;
+    let max_samples = duration.map(|duration| (duration as f64 * sample_rate as f64) as usize * channels);
+    let mut samples_read = 0;
+    let mut columns = 0;
+    let mut data = Vec::new();
+
+    source.start()?;
+    loop {
+        // Without a window to show it in, losing the input ends the run
+        match source.take_error() {
+            Some(err @ SourceError::Device(_)) => return Err(err.into()),
+            Some(err) => eprintln!("{}", err),
+            None => {}
+        }
+
+        // Check before pulling, so nothing sent just before finishing gets lost
+        let finished = source.is_finished();
+
+        data.clear();
+        source.pull_samples(&mut data);
+        if let Some(max_samples) = max_samples {
+            data.truncate(max_samples - samples_read);
+        }
+        samples_read += data.len();
+        analyzer.push_samples(&data);
+
+        while analyzer.next_frames(&mut raw) {
+            let time = analyzer.first().frame_center(columns);
+            for (signal, (raw, traces)) in raw.iter().zip(traces.iter_mut()).enumerate() {
+                write_row(&mut out, time, &name(TraceKind::Raw, signal), &raw[bins.clone()])?;
+                for (name, trace, spectrum) in traces.iter_mut() {
+                    trace.update(spectrum, raw, &ballistics, &averaging, interval);
+                    write_row(&mut out, time, name, &spectrum[bins.clone()])?;
+                }
+            }
+
+            columns += 1;
+        }
+
+        if finished || max_samples == Some(samples_read) {
+            break;
+        }
+        if data.is_empty() {
+            // Wait for a live input to catch up
+            thread::sleep(Duration::from_millis(10));
+        }
+    }
+    source.stop();
+
+    out.flush()?;
+    eprintln!("Wrote {} spectra to {}", columns, path.display());
+    if source.dropped_samples() > 0 {
+        eprintln!("{} samples were dropped on the way, so some spectra have gaps", source.dropped_samples());
+    }
+    Ok(())
+}
+
+fn write_row(out: &mut impl Write, time: f64, trace: &str, spectrum: &[f32]) -> std::io::Result<()> {
+    write!(out, "{},{}", time, trace)?;
+    for value in spectrum {
+        write!(out, ",{}", value)?;
+    }
+    writeln!(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use live_spectrum::source::{GeneratorSource, Signal};
+
+    // A generator played on both channels of a stereo input
+    struct Stereo(GeneratorSource);
+
+    impl SampleSource for Stereo {
+        fn start(&mut self) -> Result<(), SourceError> {
+            self.0.start()
+        }
+
+        fn stop(&mut self) {
+            self.0.stop()
+        }
+
+        fn sample_rate(&self) -> u32 {
+            self.0.sample_rate()
+        }
+
+        fn channels(&self) -> u16 {
+            2
+        }
+
+        fn pull_samples(&mut self, out: &mut Vec<f32>) {
+            let mut mono = Vec::new();
+            self.0.pull_samples(&mut mono);
+            out.extend(mono.iter().flat_map(|&sample| [sample, sample]));
+        }
+    }
+
+    #[test]
+    fn duration_counts_frames_not_samples() {
+        let path = std::env::temp_dir().join(format!("live_spectrum_headless_{}.csv", std::process::id()));
+        let source = Box::new(Stereo(GeneratorSource::new(Signal::Sine(1000.0), 48000, false).unwrap()));
+        let settings = AnalysisSettings::default();
+        run(source, &path, Some(1.0), &settings, &DisplaySettings::default()).unwrap();
+        let csv = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        // Every column that fits in a second of input, with a 4096 point FFT hopping 1024 at a time
+        let columns = csv.lines().filter(|line| line.split(',').nth(1) == Some("raw")).count();
+        assert_eq!(columns, (48000 - settings.fft_size) / settings.step_size + 1);
+    }
+
+    #[test]
+    fn lowest_frequency_past_the_top_bin_is_an_error() {
+        let path = std::env::temp_dir().join(format!("live_spectrum_headless_top_{}.csv", std::process::id()));
+        let run_from = |min_freq: f32| {
+            let source = Box::new(GeneratorSource::new(Signal::Sine(1000.0), 48000, false).unwrap());
+            let display = DisplaySettings { min_freq: Some(min_freq), ..DisplaySettings::default() };
+            run(source, &path, Some(0.1), &AnalysisSettings::default(), &display)
+        };
+
+        // The top bin of a 4096 point FFT at 48 kHz is 23988.3 Hz, which is still written
+        run_from(23988.0).unwrap();
+        let csv = std::fs::read_to_string(&path).unwrap();
+        assert_eq!(csv.lines().next().unwrap().split(',').count(), 3);
+
+        for min_freq in [23995.0, 24000.0] {
+            let err = run_from(min_freq).unwrap_err();
+            assert!(err.to_string().contains("above the top bin"), "{}", err);
+        }
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,131 @@
+use std::collections::VecDeque;
+
+use crate::worker::Frame;
+
+// Every recent STFT column, oldest first, for whatever needs more than the latest one
+// Keeps the last `duration` seconds of columns, but never more than `max_values` values in all,
+// so tiny hops with big FFTs can't eat all the memory
+pub struct SpectrumHistory {
+    frames: VecDeque<Frame>,
+    duration: f64,
+    max_values: usize,
+    // How many values the frames hold between them
+    values: usize,
+    // How many frames have ever been pushed, so readers can tell which ones they haven't seen yet
+    added: u64,
+}
+
+impl SpectrumHistory {
+    pub fn new(duration: f64, max_values: usize) -> Self {
+        SpectrumHistory {
+            frames: VecDeque::new(),
+            duration,
+            max_values,
+            values: 0,
+            added: 0,
+        }
+    }
+
+    // Add the newest frame, forgetting whichever have grown too old
+    pub fn push(&mut self, frame: Frame) {
+        self.values += frame_values(&frame);
+        self.frames.push_back(frame);
+        self.added += 1;
+
+        let newest = self.frames.back().map(|frame| frame.time).unwrap_or(0.0);
+        while self.frames.len() > 1 {
+            let oldest = &self.frames[0];
+            if newest - oldest.time <= self.duration && self.values <= self.max_values {
+                break;
+            }
+            self.values -= frame_values(oldest);
+            self.frames.pop_front();
+        }
+    }
+
+    // Forget every frame, like when they won't fit with the ones to come
+    pub fn clear(&mut self) {
+        self.frames.clear();
+        self.values = 0;
+    }
+
+    pub fn len(&self) -> usize {
+        self.frames.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.frames.is_empty()
+    }
+
+    pub fn latest(&self) -> Option<&Frame> {
+        self.frames.back()
+    }
+
+    // Oldest first
+    pub fn iter(&self) -> impl DoubleEndedIterator<Item = &Frame> {
+        self.frames.iter()
+    }
+
+    // How many frames have ever been pushed
+    pub fn added(&self) -> u64 {
+        self.added
+    }
+
+    // The frames pushed since `added()` was `seen`, oldest first, and the new count to remember
+    // Any that were forgotten before being read are missed
+    pub fn since(&self, seen: u64) -> (impl Iterator<Item = &Frame>, u64) {
+        let new = (self.added - seen.min(self.added)).min(self.frames.len() as u64) as usize;
+        (self.frames.iter().skip(self.frames.len() - new), self.added)
+    }
+}
+
+fn frame_values(frame: &Frame) -> usize {
+    frame.spectra.iter().map(|spectrum| spectrum.len()).sum()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn frame(time: f64, bins: usize) -> Frame {
+        Frame {
+            time,
+            spectra: vec![vec![0.0; bins]],
+        }
+    }
+
+    #[test]
+    fn old_frames_are_forgotten() {
+        let mut history = SpectrumHistory::new(1.0, 1000);
+        for i in 0..30 {
+            history.push(frame(i as f64 * 0.125, 10));
+        }
+        // The last second, from 2.625 to 3.625 seconds
+        assert_eq!(history.len(), 9);
+        assert_eq!(history.iter().next().unwrap().time, 2.625);
+
+        // Too big to keep more than 2
+        let mut history = SpectrumHistory::new(1.0, 1000);
+        for i in 0..3 {
+            history.push(frame(i as f64 * 0.125, 400));
+        }
+        assert_eq!(history.len(), 2);
+    }
+
+    #[test]
+    fn readers_see_each_frame_once() {
+        let mut history = SpectrumHistory::new(10.0, 1000);
+        history.push(frame(0.0, 1));
+        history.push(frame(0.1, 1));
+
+        let (frames, seen) = history.since(0);
+        assert_eq!(frames.count(), 2);
+        history.push(frame(0.2, 1));
+        let (frames, seen) = history.since(seen);
+        let times: Vec<_> = frames.map(|frame| frame.time).collect();
+        assert_eq!(times, vec![0.2]);
+
+        history.clear();
+        assert_eq!(history.since(seen).0.count(), 0);
+    }
+}
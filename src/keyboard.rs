@@ -0,0 +1,142 @@
+use bevy::prelude::*;
+use bevy_prototype_lyon::prelude::*;
+use live_spectrum::{band_level, AnalysisWorker, FrequencyAxis, FrequencyScale, Note, SpectrumHistory};
+
+use crate::config::Appearance;
+use crate::latest_spectrum;
+use crate::tuner::Tuner;
+
+// Lit keys go over the rest
+const LIT_Z: f32 = 0.1;
+
+// Half a semitone, so each key covers the frequencies nearer its note than any other
+const HALF_SEMITONE: f32 = 1.029_302_2;
+
+// The piano keyboard strip under the plot, shown along a note axis
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Keyboard {
+    // How loud a key's frequencies have to be to light it up, in dB
+    pub threshold: f32,
+    // Switched with K
+    pub shown: bool,
+}
+
+// The keys, by their color on a piano
+#[derive(Component)]
+pub struct WhiteKeys;
+#[derive(Component)]
+pub struct BlackKeys;
+// The keys with energy above the threshold
+#[derive(Component)]
+pub struct LitKeys;
+
+// The filters that keep the queries for each set of keys apart
+type BlackFilter = (With<BlackKeys>, Without<WhiteKeys>);
+type LitFilter = (With<LitKeys>, Without<WhiteKeys>, Without<BlackKeys>);
+
+pub fn setup_keyboard(mut commands: Commands) {
+    // Laid out and colored in by `layout_keyboard`
+    let keys = |z| GeometryBuilder::build_as(
+        &PathBuilder::new().build(),
+        DrawMode::Fill(FillMode::color(Color::NONE)),
+        Transform::from_xyz(0.0, 0.0, z),
+    );
+    commands.spawn_bundle(keys(0.0)).insert(WhiteKeys);
+    commands.spawn_bundle(keys(0.0)).insert(BlackKeys);
+    commands.spawn_bundle(keys(LIT_Z)).insert(LitKeys);
+}
+
+// Show and hide the keyboard with K
+pub fn switch_keyboard(keys: Res<Input<KeyCode>>, mut keyboard: ResMut<Keyboard>) {
+    if keys.just_pressed(KeyCode::K) {
+        keyboard.shown = !keyboard.shown;
+    }
+}
+
+// Lay the keys out under the notes they stand for, again whenever the axis, tuning or appearance change
+pub fn layout_keyboard(
+    keyboard: Res<Keyboard>,
+    axis: Res<FrequencyAxis>,
+    tuner: Res<Tuner>,
+    appearance: Res<Appearance>,
+    mut white: Query<(&mut Path, &mut DrawMode, &mut Visibility), With<WhiteKeys>>,
+    mut black: Query<(&mut Path, &mut DrawMode, &mut Visibility), BlackFilter>,
+    mut lit: Query<&mut DrawMode, LitFilter>
+) {
+    if !keyboard.is_changed() && !axis.is_changed() && !tuner.is_changed() && !appearance.is_changed() {
+        return;
+    }
+
+    let shown = keyboard.shown && axis.scale == FrequencyScale::Note;
+    // A linear axis can start at 0 Hz, infinitely many octaves down
+    let notes = if shown { axis.notes(tuner.a4) } else { Vec::new() };
+    let outline = StrokeMode::new(appearance.background, 1.0);
+
+    let (mut path, mut draw_mode, mut visibility) = white.single_mut();
+    *path = key_paths(notes.iter().filter(|note| !note.is_sharp()), &axis, &tuner, &appearance);
+    *draw_mode = DrawMode::Outlined { fill_mode: FillMode::color(appearance.scale_color), outline_mode: outline };
+    visibility.is_visible = shown;
+
+    let (mut path, mut draw_mode, mut visibility) = black.single_mut();
+    *path = key_paths(notes.iter().filter(|note| note.is_sharp()), &axis, &tuner, &appearance);
+    *draw_mode = DrawMode::Outlined {
+        fill_mode: FillMode::color(appearance.background),
+        outline_mode: StrokeMode::new(appearance.scale_color, 1.0),
+    };
+    visibility.is_visible = shown;
+
+    *lit.single_mut() = DrawMode::Outlined { fill_mode: FillMode::color(appearance.marker_color), outline_mode: outline };
+}
+
+// Light up the keys whose frequencies are louder than the threshold in the latest spectrum
+pub fn light_keys(
+    keyboard: Res<Keyboard>,
+    axis: Res<FrequencyAxis>,
+    tuner: Res<Tuner>,
+    appearance: Res<Appearance>,
+    history: Res<SpectrumHistory>,
+    analyzer: Res<AnalysisWorker>,
+    mut lit: Query<(&mut Path, &mut Visibility), With<LitKeys>>
+) {
+    let (mut path, mut visibility) = lit.single_mut();
+    visibility.is_visible = keyboard.shown && axis.scale == FrequencyScale::Note;
+    if !visibility.is_visible {
+        return;
+    }
+
+    let spectrum = match latest_spectrum(&history, &analyzer) {
+        Some(spectrum) => spectrum,
+        None => return,
+    };
+    let notes: Vec<_> = axis.notes(tuner.a4).into_iter()
+        .filter(|note| {
+            let freq = note.freq(tuner.a4);
+            band_level(&spectrum, freq / HALF_SEMITONE, freq * HALF_SEMITONE, analyzer.bin_width()) >= keyboard.threshold
+        })
+        .collect();
+    *path = key_paths(notes.iter(), &axis, &tuner, &appearance);
+}
+
+// A rectangle for each of `notes` between the plot and the frequency scale, as wide as the axis
+// gives its semitone. Black keys only come down part of the way, like on a piano
+fn key_paths<'a>(
+    notes: impl Iterator<Item = &'a Note>,
+    axis: &FrequencyAxis,
+    tuner: &Tuner,
+    appearance: &Appearance,
+) -> Path {
+    let top = appearance.plot_y_zero - 2.0;
+    let x = |freq: f32| (axis.position(freq).clamp(0.0, 1.0) - 0.5) * appearance.plot_width;
+    let mut path_builder = PathBuilder::new();
+    for note in notes {
+        let freq = note.freq(tuner.a4);
+        let (left, right) = (x(freq / HALF_SEMITONE), x(freq * HALF_SEMITONE));
+        let bottom = if note.is_sharp() { top - 10.0 } else { top - 16.0 };
+        path_builder.move_to(Vec2::new(left, top));
+        path_builder.line_to(Vec2::new(right, top));
+        path_builder.line_to(Vec2::new(right, bottom));
+        path_builder.line_to(Vec2::new(left, bottom));
+        path_builder.close();
+    }
+    path_builder.build()
+}
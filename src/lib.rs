@@ -0,0 +1,39 @@
+// The analysis behind the live_spectrum app, with no dependency on bevy, a window or an audio device
+//
+// Feed samples into a `SpectrumAnalyzer` to get spectrum frames (in dBFS) out, smooth them with
+// `update_envelope` (as fast as its `Ballistics` say), and use `bin_to_freq` to find out what frequency each bin stands for
+// (and `level_at` to read a level between them, or `band_level` the loudest over a band).
+// A `Trace` follows the frames in other ways too, averaging or holding them as its `TraceKind` says.
+// `PeakFinder` picks the loudest peaks out of a frame, with their frequencies refined to between bins,
+// and `PitchDetector` finds its fundamental, which `Note` names.
+// `WindowFunction` picks the window the analyzer applies before each FFT.
+// `MultiChannelAnalyzer` does the same for inputs with several channels, mixed or picked as a `ChannelMode` says.
+// `AnalysisWorker` runs one on a thread of its own, and `SpectrumHistory` keeps the frames it makes for a while.
+// `FrequencyAxis` and `LevelAxis` lay frequencies and levels out along a plot.
+// The inputs the app can read from are in `source`, with the `source` feature (on by default).
+
+pub mod analyzer;
+pub mod axis;
+pub mod channels;
+pub mod envelope;
+pub mod history;
+pub mod peaks;
+pub mod pitch;
+#[cfg(feature = "source")]
+pub mod source;
+#[cfg(test)]
+mod testing;
+pub mod trace;
+pub mod window;
+pub mod worker;
+
+pub use analyzer::{amplitude_to_db, band_level, bin_to_freq, freq_to_bin, level_at, SpectrumAnalyzer, MIN_DB};
+pub use axis::{FrequencyAxis, FrequencyScale, LevelAxis};
+pub use channels::{loudest, ChannelMode, MultiChannelAnalyzer};
+pub use envelope::{update_envelope, Ballistics};
+pub use history::SpectrumHistory;
+pub use peaks::{Peak, PeakFinder};
+pub use pitch::{Note, PitchDetector};
+pub use trace::{Averaging, Trace, TraceKind, TraceSet};
+pub use window::WindowFunction;
+pub use worker::{AnalysisWorker, Frame};
@@ -1,146 +1,981 @@
+use bevy::input::mouse::{MouseScrollUnit, MouseWheel};
 use bevy::prelude::*;
 use bevy_prototype_lyon::prelude::*;
-use stft::{STFT, WindowType};
-use cpal::traits::{DeviceTrait, HostTrait, StreamTrait};
-use std::sync::mpsc::{channel, Receiver};
-use std::sync::{Arc, Mutex};
+use live_spectrum::source::{
+    input_devices, DeviceId, FileSource, GeneratorSource, InputDevice, MicSource, SampleSource, SourceError,
+};
+use live_spectrum::{
+    loudest, AnalysisWorker, Averaging, Ballistics, ChannelMode, FrequencyAxis, FrequencyScale, LevelAxis,
+    MultiChannelAnalyzer, SpectrumAnalyzer, SpectrumHistory, Trace, TraceKind, TraceSet, WindowFunction, MIN_DB,
+};
+use serde::Deserialize;
 
+mod cli;
+mod config;
+mod cursor;
+mod headless;
+mod keyboard;
+mod peak_labels;
+mod tuner;
+mod waterfall;
+use cli::InputChoice;
+use config::{Appearance, Config, ConfigWatcher};
+use cursor::cursor_position;
+use tuner::Tuner;
+use waterfall::ViewMode;
 
-const DFT_OUT_SIZE: usize = 2048; // Must be power of 2
-const DFT_STEP_SIZE: usize = 1024;
-const MAX_DFT_BIN: usize = DFT_OUT_SIZE/2;
 
-const ENVELOPE_FILTER_CONST: f32 = 0.95;
+// Analysis settings used unless others are picked, see `AnalysisSettings`
+const DEFAULT_FFT_SIZE: usize = 4096;
+const DEFAULT_STEP_SIZE: usize = 1024;
+const DEFAULT_WINDOW: WindowFunction = WindowFunction::Hann;
+// The FFT sizes that can be picked at startup, or switched between at runtime
+const MIN_FFT_SIZE: usize = 256;
+const MAX_FFT_SIZE: usize = 32768;
+const MIN_STEP_SIZE: usize = 64;
 
-const PLOT_WIDTH: f32 = 800.0;
-const PLOT_Y_ZERO: f32 = -50.0;
+const GENERATOR_SAMPLE_RATE: u32 = 48000;
 
+// How often to look for a lost input device to come back, in seconds
+const RECONNECT_INTERVAL: f64 = 1.0;
+// After this many looks for a lost device, the default device will do instead
+const RECONNECT_FALLBACK_ATTEMPTS: u32 = 5;
+// How long an input error that didn't stop the input stays up, in seconds
+const INPUT_TROUBLE_TIME: f64 = 5.0;
+
+// How quickly the envelope follows the spectrum unless picked otherwise, see `Ballistics`
+const DEFAULT_ATTACK: f32 = 0.0;
+const DEFAULT_RELEASE: f32 = 0.3;
+// The envelope times that can be switched between at runtime, in seconds. Attacks shorter than
+// `MIN_ATTACK` become instant
+const MIN_ATTACK: f32 = 0.005;
+const MIN_RELEASE: f32 = 0.01;
+const MAX_ENVELOPE_TIME: f32 = 10.0;
+
+// Defaults for `Averaging`
+const DEFAULT_AVERAGE_TIME: f32 = 1.0;
+const DEFAULT_AVERAGE_FRAMES: usize = 16;
+
+// How much of the spectrum's past is kept, see `SpectrumHistory`
+const HISTORY_SECONDS: f64 = 10.0;
+const HISTORY_MAX_VALUES: usize = 1 << 24;
+
+// How many points the spectrum is resampled to across the plot
+const PLOT_POINTS: usize = 1024;
+
+// Where a log (or note) frequency axis starts, since it can't go down to 0 Hz
+const LOG_AXIS_MIN_FREQ: f32 = 20.0;
+// How much closer each notch of the mouse wheel zooms the frequency axis in
+const ZOOM_STEP: f32 = 1.25;
+// Roughly how far touchpads scroll for a notch of a mouse wheel, in pixels
+const PIXELS_PER_LINE: f32 = 20.0;
+
+// Where the cursor, the peak labels and the tuner are drawn, over the spectra
+const OVERLAY_Z: f32 = 1.0;
+
+// Levels at the bottom and top of the plot and the waterfall's color map, in dBFS
+const DEFAULT_DB_FLOOR: f32 = -100.0;
+const DEFAULT_DB_CEILING: f32 = 0.0;
+
+// Sized to the number of bins the analyzer gives
+#[derive(Component)]
+struct Spectrum(Vec<f32>);
+// One of the traces of one of the signals the analyzer picks out of the input, by its index
+#[derive(Component)]
+struct SpectrumLine {
+    signal: usize,
+    trace: Trace,
+}
+
+impl SpectrumLine {
+    // What the line's called in readouts, with its channel's number (from 1) when there are several signals
+    fn name(&self, signals: usize) -> String {
+        match signals {
+            1 => self.trace.kind().to_string(),
+            _ => format!("{} {}", self.trace.kind(), self.signal + 1),
+        }
+    }
+}
+// Everything that makes up the frequency scale, so it can be redrawn
+#[derive(Component)]
+struct Scale;
+// Likewise for the level scale and its gridlines
 #[derive(Component)]
-struct Spectrum([f32; DFT_OUT_SIZE]);
+struct LevelScale;
+// Text showing the current analysis settings
 #[derive(Component)]
-struct RawSpectrum;
+struct SettingsLabel;
+// Text showing how the microphone is doing
 #[derive(Component)]
-struct EnvelopeSpectrum;
+struct StatusLabel;
 
-struct MicSampleRate(u32);
-struct MicData(Arc<Mutex<Receiver<f32>>>);
+// Text labels that follow what they label around the plot, like the cursor's readout and the peaks'
+type Label<'a> = (&'a mut Text, &'a mut Transform, &'a mut Visibility);
+
+// How the input is analysed. Changing this resource rebuilds the analyzer
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+struct AnalysisSettings {
+    // A power of 2
+    fft_size: usize,
+    // At most the FFT size
+    #[serde(rename = "hop")]
+    step_size: usize,
+    #[serde(deserialize_with = "config::from_str")]
+    window: WindowFunction,
+    #[serde(deserialize_with = "config::from_str")]
+    channels: ChannelMode,
+}
+
+impl Default for AnalysisSettings {
+    fn default() -> Self {
+        AnalysisSettings {
+            fft_size: DEFAULT_FFT_SIZE,
+            step_size: DEFAULT_STEP_SIZE,
+            window: DEFAULT_WINDOW,
+            channels: ChannelMode::Mix,
+        }
+    }
+}
+
+impl AnalysisSettings {
+    // For an input running at `sample_rate` with `channels` channels
+    fn analyzer(&self, sample_rate: u32, channels: u16) -> MultiChannelAnalyzer {
+        let analyzer = SpectrumAnalyzer::new(self.fft_size, self.step_size, sample_rate, self.window);
+        MultiChannelAnalyzer::new(self.channels, channels as usize, analyzer)
+    }
+}
+
+// The frequencies picked in the settings, if any. Otherwise the axis covers every bin up to the
+// Nyquist frequency, starting from 0 Hz or `LOG_AXIS_MIN_FREQ` for a log axis
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+struct FrequencyRange {
+    min: Option<f32>,
+    max: Option<f32>,
+}
+
+impl FrequencyRange {
+    // The lowest and highest frequency to show, a log axis can't start at 0 Hz
+    fn limits(&self, scale: FrequencyScale, sample_rate: u32) -> (f32, f32) {
+        let min = match (scale, self.min) {
+            (FrequencyScale::Log | FrequencyScale::Note, Some(min)) if min > 0.0 => min,
+            (FrequencyScale::Log | FrequencyScale::Note, _) => LOG_AXIS_MIN_FREQ,
+            (FrequencyScale::Linear, min) => min.unwrap_or(0.0),
+        };
+        let max = self.max.unwrap_or(sample_rate as f32 / 2.0);
+        // A log axis can end up upside down if `max` is below `LOG_AXIS_MIN_FREQ`
+        (min, max.max(min + 1.0))
+    }
+
+    // How far the axis can be zoomed out, from 0 Hz (or wherever a log axis starts) up to the
+    // Nyquist frequency
+    fn bounds(&self, scale: FrequencyScale, sample_rate: u32) -> (f32, f32) {
+        let (min, max) = self.limits(scale, sample_rate);
+        let lowest = match scale {
+            FrequencyScale::Linear => 0.0,
+            FrequencyScale::Log | FrequencyScale::Note => min.min(LOG_AXIS_MIN_FREQ),
+        };
+        (lowest, max.max(sample_rate as f32 / 2.0))
+    }
+}
+
+// The device we're listening to, when the input is a microphone
+struct MicDevice(DeviceId);
+
+// How the microphone is doing. Times are in seconds since startup
+#[derive(Clone, Debug, PartialEq)]
+enum InputStatus {
+    Listening,
+    // Something went wrong, but samples still arrive
+    Trouble { error: String, until: f64 },
+    // The device went away, and we keep looking for it (or after a while, the default device)
+    Lost { error: String, attempts: u32, next_attempt: f64 },
+}
+
+// Wherever our samples come from, and how many samples it had dropped when we last looked. Non-send,
+// since cpal streams can't leave the main thread
+struct InputSource(Box<dyn SampleSource>, u64);
+
+impl InputSource {
+    fn new(source: Box<dyn SampleSource>) -> Self {
+        InputSource(source, 0)
+    }
+}
+
+impl Drop for InputSource {
+    fn drop(&mut self) {
+        self.0.stop();
+    }
+}
 
 
 fn main() {
-    App::new()
-        .insert_resource(ClearColor(Color::rgb(1.0, 1.0, 1.0)))
+    let options = cli::parse_args();
+    if options.list_devices {
+        list_devices();
+        return;
+    }
+
+    // Without a window there's no point waiting for files and generators to play in real time
+    let realtime = options.headless.is_none();
+    let mut mic_device = None;
+    let mut source: Box<dyn SampleSource> = match options.input() {
+        InputChoice::Mic(selector) => {
+            let mic = MicSource::with_device(&selector).unwrap_or_else(|err| exit_with_error(err));
+            mic_device = Some(MicDevice(mic.device_id().clone()));
+            Box::new(mic)
+        }
+        InputChoice::File(path) => Box::new(FileSource::new(&path, realtime).unwrap_or_else(|err| exit_with_error(err))),
+        InputChoice::Generator(signal) => Box::new(
+            GeneratorSource::new(signal, GENERATOR_SAMPLE_RATE, realtime).unwrap_or_else(|err| exit_with_error(err)),
+        ),
+    };
+
+    // A config file picked on the command line has to be there, the default one doesn't
+    let config_path = options.config.clone().or_else(config::default_path);
+    let mut config = match &config_path {
+        Some(path) if options.config.is_some() || path.exists() => {
+            config::load(path).unwrap_or_else(|err| exit_with_error(err))
+        }
+        _ => Config::default(),
+    };
+    options.apply(&mut config);
+    config.validate(source.sample_rate(), source.channels()).unwrap_or_else(|err| exit_with_error(err));
+
+    let display = config.display;
+    if let Some(path) = &options.headless {
+        headless::run(source, path, options.duration, &config.analysis, &display)
+            .unwrap_or_else(|err| exit_with_error(err));
+        return;
+    }
+
+    source.start().unwrap_or_else(|err| exit_with_error(err));
+    let analyzer = config.analysis.analyzer(source.sample_rate(), source.channels());
+    let range = display.frequency_range();
+
+    let mut app = App::new();
+    app.insert_resource(ClearColor(config.appearance.background))
         .insert_resource(Msaa { samples: 4 })
+        .insert_resource(frequency_axis(display.axis, &range, analyzer.sample_rate()))
+        .insert_resource(range)
+        .insert_resource(display.levels())
+        .insert_resource(AnalysisWorker::new(analyzer))
+        .insert_resource(SpectrumHistory::new(HISTORY_SECONDS, HISTORY_MAX_VALUES))
+        .insert_resource(config.analysis)
+        .insert_resource(display.traces)
+        .insert_resource(display.ballistics())
+        .insert_resource(display.averaging())
+        .insert_resource(display.view)
+        .insert_resource(display.colormap)
+        .insert_resource(cursor::Markers::default())
+        .insert_resource(display.peak_labels())
+        .insert_resource(display.tuner())
+        .insert_resource(display.keyboard())
+        .insert_resource(config.appearance.clone())
+        // Non-send, so the input has to be added directly rather than from a startup system
+        .insert_non_send_resource(InputSource::new(source))
         .add_plugins(DefaultPlugins)
         .add_plugin(ShapePlugin)
-        .add_startup_system(setup_mic.exclusive_system())
+        .add_startup_system(log_input)
         .add_startup_system(setup_spectra)
-        .add_startup_system(draw_scale)
-        .add_system(mic_input)
-        .add_system(envelope_spectrum)
+        .add_startup_system(waterfall::setup_waterfall)
+        .add_startup_system(cursor::setup_cursor)
+        .add_startup_system(peak_labels::setup_peaks)
+        .add_startup_system(tuner::setup_tuner)
+        .add_startup_system(keyboard::setup_keyboard)
+        .add_system(source_input)
+        .add_system(switch_analysis)
+        .add_system(switch_envelope)
+        .add_system(switch_traces)
+        .add_system(show_traces)
+        .add_system(apply_analysis)
+        .add_system(apply_traces)
+        .add_system(update_traces)
         .add_system(animate_spectra)
-        .add_system(bevy::input::system::exit_on_esc_system)
-        .run();
-}
+        .add_system(apply_appearance)
+        .add_system(switch_axis)
+        .add_system(zoom_axis)
+        .add_system(cursor::style_cursor)
+        .add_system(cursor::update_cursor)
+        .add_system(cursor::pin_markers)
+        .add_system(cursor::draw_markers)
+        .add_system(peak_labels::style_peaks)
+        .add_system(peak_labels::switch_peaks)
+        .add_system(peak_labels::draw_peaks)
+        .add_system(tuner::style_tuner)
+        .add_system(tuner::switch_tuner)
+        .add_system(tuner::update_tuner)
+        .add_system(keyboard::switch_keyboard)
+        .add_system(keyboard::layout_keyboard)
+        .add_system(keyboard::light_keys)
+        .add_system(draw_scale)
+        .add_system(draw_level_scale)
+        .add_system(waterfall::update_waterfall)
+        .add_system(waterfall::switch_view)
+        .add_system(waterfall::apply_view)
+        .add_system(bevy::input::system::exit_on_esc_system);
 
-// Setup gathering of microphone data
-// We need exclusive world access to add non-send resources
-fn setup_mic(world: &mut World) {
-    // Use channel to send data from the mic callback thread back to our worker threads
-    let (tx, rx) = channel();
+    if let Some(device) = mic_device {
+        app.insert_resource(device)
+            .insert_resource(InputStatus::Listening)
+            .add_system(watch_input)
+            .add_system(switch_device)
+            .add_system(show_status);
+    }
+    if let Some(path) = config_path {
+        app.insert_resource(ConfigWatcher::new(path, options, config))
+            .add_system(config::reload_config);
+    }
 
-    let host = cpal::default_host();
-    let device = host.default_input_device().expect("No microphone found");
+    app.run();
+}
 
-    let config = device
-        .default_input_config()
-        .expect("No supported mic config");
+fn exit_with_error(err: impl std::fmt::Display) -> ! {
+    eprintln!("{}", err);
+    std::process::exit(1);
+}
 
-    // Save the sample rate so we can use it to find frequencies later
-    let sample_rate = config.sample_rate();
+// The latest spectrum, unless the analyzer has changed since
+// With several signals, each bin has the loudest of them
+fn latest_spectrum(history: &SpectrumHistory, analyzer: &AnalysisWorker) -> Option<Vec<f32>> {
+    history.latest()
+        .filter(|frame| frame.spectra[0].len() == analyzer.bins())
+        .map(|frame| loudest(&frame.spectra))
+}
 
-    let stream = device.build_input_stream(
-        &config.into(),
-        move |data: &[f32], _: &cpal::InputCallbackInfo| {
-            for val in data {
-                tx.send(*val).unwrap();
-            }
-        },
-        move |_| {},
-    ).unwrap();
-    stream.play().unwrap();
+// The frequency axis covering `range` of an input running at `sample_rate`
+fn frequency_axis(scale: FrequencyScale, range: &FrequencyRange, sample_rate: u32) -> FrequencyAxis {
+    let (min, max) = range.limits(scale, sample_rate);
+    FrequencyAxis::new(scale, min, max)
+}
 
-    world.insert_non_send_resource(stream);
-    world.insert_resource(MicSampleRate(sample_rate.0));
-    world.insert_resource(MicData(Arc::new(Mutex::new(rx))));
-    world.insert_resource(STFT::<f32>::new(WindowType::Hanning, 2*DFT_OUT_SIZE, DFT_STEP_SIZE));
+// Print every input device, numbered for --device, with the configs it supports
+fn list_devices() {
+    let devices = input_devices();
+    if devices.is_empty() {
+        println!("No input devices found");
+    }
+    for (index, device) in devices.iter().enumerate() {
+        let default = if device.is_default { ", default" } else { "" };
+        println!("{}: {} ({}{})", index, device.id.name, device.id.host.name(), default);
+        for config in device.configs() {
+            println!("     {}", config);
+        }
+    }
+}
 
+fn log_input(source: NonSend<InputSource>, device: Option<Res<MicDevice>>) {
+    if let Some(device) = device {
+        info!("Listening to {}", device.0);
+    }
+    info!("Input running at {} Hz with {} channel(s)", source.0.sample_rate(), source.0.channels());
 }
 
-// Setup the spectra we have and the paths we'll use for associated graphs
+// Setup the camera and the settings label. The spectra come and go with the signals we analyse,
+// see `apply_analysis`
 fn setup_spectra(mut commands: Commands) {
     commands.spawn_bundle(OrthographicCameraBundle::new_2d());
 
-    commands.spawn().insert(Spectrum([0.0; DFT_OUT_SIZE])).insert(RawSpectrum);
+    // Filled in by `apply_analysis` and `show_traces`, and placed by `apply_appearance`
+    commands.spawn_bundle(Text2dBundle {
+        text: Text {
+            sections: vec![TextSection::default(), TextSection::default()],
+            alignment: TextAlignment {
+                vertical: VerticalAlign::Center,
+                horizontal: HorizontalAlign::Right,
+            },
+        },
+        ..default()
+    }).insert(SettingsLabel);
+
+    // Filled in by `show_status` when the input is a microphone
+    commands.spawn_bundle(Text2dBundle {
+        text: Text::with_section(
+            "",
+            TextStyle::default(),
+            TextAlignment {
+                vertical: VerticalAlign::Center,
+                horizontal: HorizontalAlign::Left,
+            },
+        ),
+        ..default()
+    }).insert(StatusLabel);
+}
+
+// Restyle everything that isn't redrawn from scratch when the appearance changes
+fn apply_appearance(
+    appearance: Res<Appearance>,
+    asset_server: Res<AssetServer>,
+    mut clear_color: ResMut<ClearColor>,
+    analyzer: Res<AnalysisWorker>,
+    mut line_query: Query<(&mut DrawMode, &SpectrumLine)>,
+    mut label_query: Query<(&mut Text, &mut Transform), With<SettingsLabel>>,
+    mut status_query: Query<&mut Transform, (With<StatusLabel>, Without<SettingsLabel>)>
+) {
+    if !appearance.is_changed() {
+        return;
+    }
+
+    clear_color.0 = appearance.background;
+    for (mut draw_mode, line) in line_query.iter_mut() {
+        let color = appearance.trace_color(line.trace.kind(), line.signal, analyzer.signals());
+        *draw_mode = DrawMode::Stroke(StrokeMode::new(color, 1.0));
+    }
+
+    let (mut text, mut transform) = label_query.single_mut();
+    for section in text.sections.iter_mut() {
+        section.style = TextStyle {
+            font: asset_server.load(&appearance.font),
+            font_size: 12.0,
+            color: appearance.scale_color,
+        };
+    }
+    transform.translation = Vec3::new(
+        appearance.plot_width / 2.0,
+        appearance.plot_y_zero + appearance.plot_height + 20.0,
+        0.0,
+    );
+    status_query.single_mut().translation = Vec3::new(
+        -appearance.plot_width / 2.0 + 10.0,
+        appearance.plot_y_zero + appearance.plot_height + 20.0,
+        0.0,
+    );
+}
+
+// Hand our input data over to the STFT, and add whatever frequency information it's made to the history
+// The STFT itself runs on the worker's own thread, so big FFTs don't hold up drawing
+fn source_input(
+    mut history: ResMut<SpectrumHistory>,
+    analyzer: Res<AnalysisWorker>,
+    mut source: NonSendMut<InputSource>
+) {
+    let mut data = Vec::new();
+    source.0.pull_samples(&mut data);
+    analyzer.push_samples(&data);
+
+    let mut frames = Vec::new();
+    analyzer.take_frames(&mut frames);
+    for frame in frames {
+        history.push(frame);
+    }
+}
+
+// Switch to the next input device with D, when listening to a microphone
+fn switch_device(
+    keys: Res<Input<KeyCode>>,
+    mut device: ResMut<MicDevice>,
+    mut status: ResMut<InputStatus>,
+    mut source: NonSendMut<InputSource>,
+    mut settings: ResMut<AnalysisSettings>,
+    mut axis: ResMut<FrequencyAxis>,
+    range: Res<FrequencyRange>
+) {
+    if !keys.just_pressed(KeyCode::D) {
+        return;
+    }
+
+    // Look the devices up again, since some may have been plugged in or out
+    let devices = input_devices();
+    let next = match devices.iter().position(|candidate| candidate.id == device.0) {
+        Some(current) => (current + 1) % devices.len(),
+        None => 0,
+    };
+    let next = match devices.into_iter().nth(next) {
+        Some(next) => next,
+        None => {
+            warn!("No input devices found");
+            return;
+        }
+    };
+
+    let name = next.id.clone();
+    match listen_to(next, &mut source, &mut settings, &mut axis, &range) {
+        Ok(()) => {
+            device.0 = name;
+            *status = InputStatus::Listening;
+        }
+        Err(err) => warn!("Couldn't switch to {}: {}", name, err),
+    }
+}
+
+// Make `device` our input instead of whatever it was, and redo everything that depends on the input
+fn listen_to(
+    device: InputDevice,
+    source: &mut InputSource,
+    settings: &mut ResMut<AnalysisSettings>,
+    axis: &mut FrequencyAxis,
+    range: &FrequencyRange
+) -> Result<(), SourceError> {
+    let mut mic = MicSource::from_device(device)?;
+    mic.start()?;
+    info!("Switched to {}, running at {} Hz with {} channel(s)", mic.device_id(), mic.sample_rate(), mic.channels());
+
+    // The new device may run at a different rate, or have different channels, so everything that
+    // depends on it has to be redone
+    *axis = frequency_axis(axis.scale, range, mic.sample_rate());
+    if let ChannelMode::Single(channel) = settings.channels {
+        if channel >= mic.channels() as usize {
+            info!("{} has no channel {}, mixing its channels instead", mic.device_id(), channel + 1);
+            settings.channels = ChannelMode::Mix;
+        }
+    }
+    settings.set_changed();
+    // Replacing the old source drops it, which closes its stream. The new one counts its dropped
+    // samples from zero
+    *source = InputSource::new(Box::new(mic));
+    Ok(())
+}
+
+// Report the microphone's errors, and samples dropped because we fell behind it. When it goes away,
+// keep looking for it to come back, and if it doesn't after a while, the default device will do
+fn watch_input(
+    time: Res<Time>,
+    mut device: ResMut<MicDevice>,
+    mut status: ResMut<InputStatus>,
+    mut source: NonSendMut<InputSource>,
+    mut settings: ResMut<AnalysisSettings>,
+    mut axis: ResMut<FrequencyAxis>,
+    range: Res<FrequencyRange>
+) {
+    let now = time.seconds_since_startup();
+
+    match source.0.take_error() {
+        Some(SourceError::Device(error)) => {
+            warn!("Lost {}: {}", device.0, error);
+            *status = InputStatus::Lost { error, attempts: 0, next_attempt: now + RECONNECT_INTERVAL };
+            // Let the spectra fall away, rather than freezing on the last one
+            settings.set_changed();
+            return;
+        }
+        Some(error) => {
+            warn!("{}: {}", device.0, error);
+            *status = InputStatus::Trouble { error: error.to_string(), until: now + INPUT_TROUBLE_TIME };
+            return;
+        }
+        None => {}
+    }
+
+    let dropped = source.0.dropped_samples();
+    let newly_dropped = dropped.saturating_sub(source.1);
+    source.1 = dropped;
+    if newly_dropped > 0 {
+        let error = format!("dropped {} samples, the app is falling behind", newly_dropped);
+        // Only log the first of a run of these
+        if !matches!(*status, InputStatus::Trouble { .. }) {
+            warn!("{}: {}", device.0, error);
+        }
+        *status = InputStatus::Trouble { error, until: now + INPUT_TROUBLE_TIME };
+        return;
+    }
+
+    // Only touch the status when something changes, since that redraws it
+    let attempts = match *status {
+        InputStatus::Trouble { until, .. } if now > until => {
+            *status = InputStatus::Listening;
+            return;
+        }
+        InputStatus::Lost { attempts, next_attempt, .. } if now > next_attempt => attempts,
+        _ => return,
+    };
+    if let InputStatus::Lost { attempts, next_attempt, .. } = &mut *status {
+        *attempts += 1;
+        *next_attempt = now + RECONNECT_INTERVAL;
+    }
+
+    let devices = input_devices();
+    let found = devices.iter().position(|candidate| candidate.id == device.0).or_else(|| {
+        let fallback = devices.iter().position(|candidate| candidate.is_default);
+        fallback.filter(|_| attempts >= RECONNECT_FALLBACK_ATTEMPTS)
+    });
+    let next = match found.and_then(|index| devices.into_iter().nth(index)) {
+        Some(next) => next,
+        None => return,
+    };
+
+    let name = next.id.clone();
+    match listen_to(next, &mut source, &mut settings, &mut axis, &range) {
+        Ok(()) => {
+            device.0 = name;
+            *status = InputStatus::Listening;
+        }
+        // It may well be back before it's ready, so just try again later
+        Err(err) => debug!("Couldn't reconnect to {}: {}", name, err),
+    }
+}
+
+// Show how the microphone is doing above the top left of the plot
+fn show_status(
+    status: Res<InputStatus>,
+    device: Res<MicDevice>,
+    appearance: Res<Appearance>,
+    asset_server: Res<AssetServer>,
+    mut label: Query<&mut Text, With<StatusLabel>>
+) {
+    if !status.is_changed() && !device.is_changed() && !appearance.is_changed() {
+        return;
+    }
+
+    let (text, color) = match &*status {
+        InputStatus::Listening => (format!("Listening to {}", device.0), appearance.scale_color),
+        InputStatus::Trouble { error, .. } => (format!("{}: {}", device.0, error), appearance.error_color),
+        InputStatus::Lost { error, .. } => (
+            format!("Lost {}: {}, waiting for it to come back", device.0, error),
+            appearance.error_color,
+        ),
+    };
+    let mut label = label.single_mut();
+    label.sections[0].value = text;
+    label.sections[0].style = TextStyle {
+        font: asset_server.load(&appearance.font),
+        font_size: 12.0,
+        color,
+    };
+}
+
+// Change the analysis settings from the keyboard: W cycles through the windows, [ and ] halve and
+// double the FFT size, , and . halve and double the hop, and M cycles through the ways of
+// analysing the input's channels
+fn switch_analysis(
+    keys: Res<Input<KeyCode>>,
+    analyzer: Res<AnalysisWorker>,
+    mut settings: ResMut<AnalysisSettings>
+) {
+    // Only touch the settings when something changes, since that rebuilds the analyzer
+    let mut new_settings = *settings;
+
+    if keys.just_pressed(KeyCode::W) {
+        new_settings.window = new_settings.window.next();
+    }
+    if keys.just_pressed(KeyCode::LBracket) {
+        new_settings.fft_size = (new_settings.fft_size / 2).max(MIN_FFT_SIZE);
+    }
+    if keys.just_pressed(KeyCode::RBracket) {
+        new_settings.fft_size = (new_settings.fft_size * 2).min(MAX_FFT_SIZE);
+    }
+    if keys.just_pressed(KeyCode::Comma) {
+        new_settings.step_size = (new_settings.step_size / 2).max(MIN_STEP_SIZE);
+    }
+    if keys.just_pressed(KeyCode::Period) {
+        new_settings.step_size *= 2;
+    }
+    new_settings.step_size = new_settings.step_size.min(new_settings.fft_size);
+    if keys.just_pressed(KeyCode::M) {
+        new_settings.channels = new_settings.channels.next(analyzer.channels());
+    }
 
+    if new_settings != *settings {
+        *settings = new_settings;
+    }
+}
+
+// Rebuild the analyzer, and the spectra to match, whenever the settings (or the input) change
+#[allow(clippy::too_many_arguments)]
+fn apply_analysis(
+    mut commands: Commands,
+    settings: Res<AnalysisSettings>,
+    source: NonSend<InputSource>,
+    appearance: Res<Appearance>,
+    view: Res<ViewMode>,
+    mut analyzer: ResMut<AnalysisWorker>,
+    mut history: ResMut<SpectrumHistory>,
+    traces: Res<TraceSet>,
+    old_spectra: Query<Entity, With<SpectrumLine>>,
+    mut label: Query<&mut Text, With<SettingsLabel>>
+) {
+    if !settings.is_changed() {
+        return;
+    }
+
+    analyzer.replace(settings.analyzer(source.0.sample_rate(), source.0.channels()));
+    // The columns we have won't fit with the new ones
+    history.clear();
+
+    // One line for each trace of each signal, since their number may have changed
+    for entity in old_spectra.iter() {
+        commands.entity(entity).despawn();
+    }
+    for kind in traces.iter() {
+        for signal in 0..analyzer.signals() {
+            spawn_line(&mut commands, kind, signal, &analyzer, &appearance, *view);
+        }
+    }
+
+    let channels = match (settings.channels, analyzer.channels()) {
+        (_, 1) => "mono".to_string(),
+        (ChannelMode::Mix, channels) => format!("{} channels mixed", channels),
+        (ChannelMode::Single(channel), channels) => format!("channel {} of {}", channel.min(channels - 1) + 1, channels),
+        (ChannelMode::Separate, channels) => format!("{} channels", channels),
+    };
+    let description = format!(
+        "FFT {} ({:.1} Hz bins), hop {}, {} window, {}",
+        settings.fft_size, analyzer.bin_width(), settings.step_size, settings.window, channels
+    );
+    info!("Analysing with {}", description);
+    label.single_mut().sections[0].value = description;
+}
+
+// Add and remove lines as traces are switched on and off, leaving the others be
+fn apply_traces(
+    mut commands: Commands,
+    traces: Res<TraceSet>,
+    settings: Res<AnalysisSettings>,
+    analyzer: Res<AnalysisWorker>,
+    appearance: Res<Appearance>,
+    view: Res<ViewMode>,
+    lines: Query<(Entity, &SpectrumLine)>
+) {
+    // `apply_analysis` starts every line over when the settings change
+    if !traces.is_changed() || settings.is_changed() {
+        return;
+    }
+
+    for (entity, line) in lines.iter() {
+        if !traces.contains(line.trace.kind()) {
+            commands.entity(entity).despawn();
+        }
+    }
+    for kind in traces.iter() {
+        if !lines.iter().any(|(_, line)| line.trace.kind() == kind) {
+            for signal in 0..analyzer.signals() {
+                spawn_line(&mut commands, kind, signal, &analyzer, &appearance, *view);
+            }
+        }
+    }
+}
+
+// Add the line for trace `kind` of signal `signal`, starting from nothing
+fn spawn_line(
+    commands: &mut Commands,
+    kind: TraceKind,
+    signal: usize,
+    analyzer: &AnalysisWorker,
+    appearance: &Appearance,
+    view: ViewMode
+) {
+    let color = appearance.trace_color(kind, signal, analyzer.signals());
     commands.spawn_bundle(GeometryBuilder::build_as(
         &PathBuilder::new().build(),
-        DrawMode::Stroke(StrokeMode::new(Color::BLACK, 1.0)),
+        DrawMode::Stroke(StrokeMode::new(color, 1.0)),
         Transform::default(),
-    )).insert(Spectrum([0.0; DFT_OUT_SIZE])).insert(EnvelopeSpectrum);
+    ))
+    .insert(Visibility { is_visible: view != ViewMode::Waterfall })
+    .insert(Spectrum(vec![MIN_DB; analyzer.bins()]))
+    .insert(SpectrumLine { signal, trace: Trace::new(kind) });
+}
+
+// Work the raw spectra from the input into every trace, one column at a time
+fn update_traces(
+    history: Res<SpectrumHistory>,
+    mut seen: Local<u64>,
+    mut line_query: Query<(&mut Spectrum, &mut SpectrumLine)>,
+    analyzer: Res<AnalysisWorker>,
+    ballistics: Res<Ballistics>,
+    averaging: Res<Averaging>
+) {
+    let (frames, added) = history.since(*seen);
+    *seen = added;
+    let interval = analyzer.frame_interval();
+
+    for frame in frames {
+        for (mut spectrum, mut line) in line_query.iter_mut() {
+            let line = &mut *line;
+            // Lines for the old signals linger for a frame after the analysis changes
+            match frame.spectra.get(line.signal) {
+                Some(raw) if raw.len() == spectrum.0.len() => {
+                    line.trace.update(&mut spectrum.0, raw, &ballistics, &averaging, interval)
+                }
+                _ => {}
+            }
+        }
+    }
+}
+
+// Switch traces on and off with 1 to 6, in `TraceKind::ALL` order, and start the averages and
+// holds over with R
+fn switch_traces(
+    keys: Res<Input<KeyCode>>,
+    mut traces: ResMut<TraceSet>,
+    mut line_query: Query<&mut SpectrumLine>
+) {
+    const KEYS: [KeyCode; 6] = [KeyCode::Key1, KeyCode::Key2, KeyCode::Key3, KeyCode::Key4, KeyCode::Key5, KeyCode::Key6];
+    for (key, kind) in KEYS.into_iter().zip(TraceKind::ALL) {
+        if keys.just_pressed(key) {
+            traces.toggle(kind);
+        }
+    }
+
+    if keys.just_pressed(KeyCode::R) {
+        for mut line in line_query.iter_mut() {
+            line.trace.reset();
+        }
+    }
+}
+
+// Change how quickly the envelope follows the spectrum: - and = halve and double the release time,
+// and with shift held, the attack time
+fn switch_envelope(keys: Res<Input<KeyCode>>, mut ballistics: ResMut<Ballistics>) {
+    let (shorter, longer) = (keys.just_pressed(KeyCode::Minus), keys.just_pressed(KeyCode::Equals));
+    if !shorter && !longer {
+        return;
+    }
+
+    if keys.any_pressed([KeyCode::LShift, KeyCode::RShift]) {
+        let attack = if shorter { ballistics.attack / 2.0 } else { ballistics.attack * 2.0 };
+        ballistics.attack = if attack >= MIN_ATTACK {
+            attack.min(MAX_ENVELOPE_TIME)
+        } else if longer {
+            MIN_ATTACK
+        } else {
+            0.0
+        };
+    } else {
+        let release = if shorter { ballistics.release / 2.0 } else { ballistics.release * 2.0 };
+        ballistics.release = release.clamp(MIN_RELEASE, MAX_ENVELOPE_TIME);
+    }
 }
 
-// Take our microphone data and get frequency information from it using the STFT
-fn mic_input(
-    mut query: Query<&mut Spectrum, With<RawSpectrum>>,
-    mut stft: ResMut<STFT::<f32>>,
-    mic_data: Res<MicData>
+// List the traces shown, and how they follow the spectrum, on a line of the settings label of their own
+fn show_traces(
+    traces: Res<TraceSet>,
+    ballistics: Res<Ballistics>,
+    averaging: Res<Averaging>,
+    mut label: Query<&mut Text, With<SettingsLabel>>
 ) {
-    let mut spectrum = query.single_mut();
-    let data: Vec<f32> = mic_data.0.lock().unwrap().try_iter().collect();
-    stft.append_samples(&data);
+    if !traces.is_changed() && !ballistics.is_changed() && !averaging.is_changed() {
+        return;
+    }
+
+    let shown: Vec<_> = traces.iter().map(|kind| match kind {
+        TraceKind::Raw => "raw".to_string(),
+        TraceKind::Envelope => format!(
+            "envelope (attack {}, release {})",
+            format_seconds(ballistics.attack),
+            format_seconds(ballistics.release)
+        ),
+        TraceKind::Exponential => format!("{} average", format_seconds(averaging.time)),
+        TraceKind::Linear => format!("{} frame average", averaging.frames),
+        TraceKind::MaxHold => "max hold".to_string(),
+        TraceKind::MinHold => "min hold".to_string(),
+    }).collect();
+    let description = if shown.is_empty() { "no traces".to_string() } else { shown.join(", ") };
+    info!("Showing {}", description);
+    label.single_mut().sections[1].value = format!("\n{}", description);
+}
 
-    while stft.contains_enough_to_compute() {
-        stft.compute_column(&mut spectrum.0[..]);
-        // throw away data if it wasn't read by animate_spectrum fast enough
-        stft.move_to_next_column();
+fn format_seconds(seconds: f32) -> String {
+    if seconds < 1.0 {
+        format!("{:.0} ms", seconds * 1000.0)
+    } else {
+        format!("{:.1} s", seconds)
+    }
+}
+
+// Switch between linear, log and note frequency axes with L
+fn switch_axis(
+    keys: Res<Input<KeyCode>>,
+    mut axis: ResMut<FrequencyAxis>,
+    range: Res<FrequencyRange>,
+    analyzer: Res<AnalysisWorker>
+) {
+    if keys.just_pressed(KeyCode::L) {
+        let scale = match axis.scale {
+            FrequencyScale::Linear => FrequencyScale::Log,
+            FrequencyScale::Log => FrequencyScale::Note,
+            FrequencyScale::Note => FrequencyScale::Linear,
+        };
+        *axis = frequency_axis(scale, &range, analyzer.sample_rate());
     }
 }
 
-// Filter the raw spectrum from the microphone
-fn envelope_spectrum(
-    mic_query: Query<&Spectrum, (With<RawSpectrum>, Without<EnvelopeSpectrum>)>,
-    mut envelope_query: Query<&mut Spectrum, With<EnvelopeSpectrum>>
+// Zoom the frequency axis in and out around the mouse with the wheel, drag it along with the left
+// button, and go back to the range from the settings with Z
+#[allow(clippy::too_many_arguments)]
+fn zoom_axis(
+    mut wheel: EventReader<MouseWheel>,
+    buttons: Res<Input<MouseButton>>,
+    keys: Res<Input<KeyCode>>,
+    windows: Res<Windows>,
+    mut dragging_from: Local<Option<f32>>,
+    mut axis: ResMut<FrequencyAxis>,
+    range: Res<FrequencyRange>,
+    appearance: Res<Appearance>,
+    analyzer: Res<AnalysisWorker>
 ) {
-    let mic = mic_query.single();
-    let mut envelope = envelope_query.single_mut();
+    let lines: f32 = wheel.iter().map(|event| match event.unit {
+        MouseScrollUnit::Line => event.y,
+        MouseScrollUnit::Pixel => event.y / PIXELS_PER_LINE,
+    }).sum();
 
-    for i in 0..envelope.0.len() {
-        envelope.0[i] =
-            (envelope.0[i]*ENVELOPE_FILTER_CONST + mic.0[i]*(1.0-ENVELOPE_FILTER_CONST)).max(mic.0[i]);
+    if keys.just_pressed(KeyCode::Z) {
+        *axis = frequency_axis(axis.scale, &range, analyzer.sample_rate());
+        return;
+    }
+
+    // Where the mouse is along the plot, from 0 at its left edge to 1 at its right
+    let cursor = cursor_position(&windows);
+    let position = cursor.map(|cursor| cursor.x / appearance.plot_width + 0.5);
+    let (bottom, top) = (appearance.plot_y_zero, appearance.plot_y_zero + appearance.plot_height);
+    let over_plot = cursor
+        .filter(|cursor| (bottom..=top).contains(&cursor.y))
+        .and(position)
+        .filter(|position| (0.0..=1.0).contains(position));
+    let (lowest, highest) = range.bounds(axis.scale, analyzer.sample_rate());
+    let mut new_axis = *axis;
+
+    if let Some(position) = over_plot {
+        if lines != 0.0 {
+            new_axis = new_axis.zoom(position, ZOOM_STEP.powf(-lines), lowest, highest);
+        }
+        if buttons.just_pressed(MouseButton::Left) {
+            *dragging_from = Some(position);
+        }
+    }
+    if !buttons.pressed(MouseButton::Left) {
+        *dragging_from = None;
+    }
+    // Keep dragging even once the mouse leaves the plot
+    if let (Some(from), Some(to)) = (*dragging_from, position) {
+        new_axis = new_axis.pan(to - from, lowest, highest);
+        *dragging_from = Some(to);
+    }
+
+    // Only touch the axis when it moves, since that redraws the scale and the waterfall
+    if new_axis != *axis {
+        *axis = new_axis;
     }
 }
 
-// Draw the scale for our graph
+// Draw the scale for our graph, again whenever the axis or appearance changes (or the tuning, for
+// a note axis)
 fn draw_scale(
     mut commands: Commands,
     asset_server: Res<AssetServer>,
-    sample_rate: Res<MicSampleRate>
+    axis: Res<FrequencyAxis>,
+    tuner: Res<Tuner>,
+    appearance: Res<Appearance>,
+    old_scale: Query<Entity, With<Scale>>
 ) {
+    if !axis.is_changed() && !appearance.is_changed() && !tuner.is_changed() {
+        return;
+    }
+    for entity in old_scale.iter() {
+        commands.entity(entity).despawn();
+    }
+
     let mut paths = Vec::new();
     let mut labels = Vec::new();
 
     let mut path_builder = PathBuilder::new();
 
-    let width = PLOT_WIDTH / 2.0;
-    let height = PLOT_Y_ZERO - 30.0;
-    let num_ticks = 20;
+    let width = appearance.plot_width / 2.0;
+    let height = appearance.plot_y_zero - 30.0;
 
-    let color = Color::GRAY;
-    let font = asset_server.load("fonts/EBGaramond-Medium.ttf");
+    let color = appearance.scale_color;
+    let font = asset_server.load(&appearance.font);
     let text_style = TextStyle {
         font,
         font_size: 12.0,
-        color: color,
+        color,
     };
     let text_alignment = TextAlignment {
         vertical: VerticalAlign::Center,
@@ -152,10 +987,18 @@ fn draw_scale(
     path_builder.line_to(Vec2::new(width, height));
     paths.push(path_builder.build());
 
-    labels.push(("Hz".to_string(), Vec3::new(-width - 20.0, height, 0.0)));
+    // A note axis is labelled with the notes, tuned like the tuner
+    let ticks: Vec<_> = match axis.scale {
+        FrequencyScale::Note => axis.note_ticks(tuner.a4).into_iter()
+            .map(|note| (note.freq(tuner.a4), note.to_string()))
+            .collect(),
+        _ => axis.ticks().into_iter().map(|freq| (freq, axis.tick_label(freq))).collect(),
+    };
+    let unit = if axis.scale == FrequencyScale::Note { "Note" } else { "Hz" };
+    labels.push((unit.to_string(), Vec3::new(-width - 20.0, height, 0.0)));
 
-    for i in 0..=num_ticks {
-        let tick_pos = -width + (((i as f32) / (num_ticks as f32)) * width * 2.0);
+    for (freq_hz, label) in ticks {
+        let tick_pos = -width + axis.position(freq_hz) * width * 2.0;
 
         // Draw tick marks
         let mut path_builder = PathBuilder::new();
@@ -164,10 +1007,7 @@ fn draw_scale(
         paths.push(path_builder.build());
 
         // Draw labels
-        let freq_hz = ((i as f32) / (num_ticks as f32))
-            * ((MAX_DFT_BIN as f32) / (2.0 * DFT_OUT_SIZE as f32))
-            * (sample_rate.0 as f32);
-        labels.push((format!("{:.0}", freq_hz), Vec3::new(tick_pos, height-20.0, 0.0)));
+        labels.push((label, Vec3::new(tick_pos, height-20.0, 0.0)));
     }
 
     for path in paths.iter() {
@@ -175,7 +1015,7 @@ fn draw_scale(
             path,
             DrawMode::Stroke(StrokeMode::new(color, 1.0)),
             Transform::default(),
-        ));
+        )).insert(Scale);
     }
 
     for (text, pos) in labels {
@@ -183,21 +1023,106 @@ fn draw_scale(
             text: Text::with_section(text, text_style.clone(), text_alignment),
             transform: Transform::from_translation(pos),
             ..default()
-        });
+        }).insert(Scale);
     }
 
 }
 
+// Draw the level scale up the left of the graph, with gridlines across it, again whenever the
+// levels or appearance change
+fn draw_level_scale(
+    mut commands: Commands,
+    asset_server: Res<AssetServer>,
+    levels: Res<LevelAxis>,
+    appearance: Res<Appearance>,
+    view: Res<ViewMode>,
+    old_scale: Query<Entity, With<LevelScale>>
+) {
+    if !levels.is_changed() && !appearance.is_changed() {
+        return;
+    }
+    for entity in old_scale.iter() {
+        commands.entity(entity).despawn();
+    }
+
+    let width = appearance.plot_width / 2.0;
+    let (bottom, height) = (appearance.plot_y_zero, appearance.plot_height);
+    let (color, grid_color) = (appearance.scale_color, appearance.grid_color);
+    let visibility = Visibility { is_visible: *view != ViewMode::Waterfall };
+
+    let text_style = TextStyle {
+        font: asset_server.load(&appearance.font),
+        font_size: 12.0,
+        color,
+    };
+    let text_alignment = TextAlignment {
+        vertical: VerticalAlign::Center,
+        horizontal: HorizontalAlign::Right,
+    };
+
+    // Line containing tick marks
+    let mut path_builder = PathBuilder::new();
+    path_builder.move_to(Vec2::new(-width, bottom));
+    path_builder.line_to(Vec2::new(-width, bottom + height));
+    let mut paths = vec![(path_builder.build(), color)];
+
+    let mut labels = vec![("dBFS".to_string(), Vec3::new(-width - 8.0, bottom + height + 20.0, 0.0))];
+
+    for db in levels.ticks() {
+        let tick_pos = bottom + levels.position(db) * height;
+
+        // Draw tick marks, and gridlines across the plot
+        let mut path_builder = PathBuilder::new();
+        path_builder.move_to(Vec2::new(-width - 5.0, tick_pos));
+        path_builder.line_to(Vec2::new(-width, tick_pos));
+        paths.push((path_builder.build(), color));
+
+        let mut path_builder = PathBuilder::new();
+        path_builder.move_to(Vec2::new(-width, tick_pos));
+        path_builder.line_to(Vec2::new(width, tick_pos));
+        paths.push((path_builder.build(), grid_color));
+
+        // Draw labels
+        labels.push((format!("{}", db), Vec3::new(-width - 8.0, tick_pos, 0.0)));
+    }
+
+    // Gridlines go behind the spectra
+    for (path, color) in paths.iter() {
+        commands.spawn_bundle(GeometryBuilder::build_as(
+            path,
+            DrawMode::Stroke(StrokeMode::new(*color, 1.0)),
+            Transform::from_xyz(0.0, 0.0, -1.0),
+        )).insert(visibility.clone()).insert(LevelScale);
+    }
+
+    for (text, pos) in labels {
+        commands.spawn_bundle(Text2dBundle {
+            text: Text::with_section(text, text_style.clone(), text_alignment),
+            transform: Transform::from_translation(pos),
+            ..default()
+        }).insert(visibility.clone()).insert(LevelScale);
+    }
+}
+
 // Actually draw the graph for each frame
-fn animate_spectra(mut query: Query<(&mut Path, &Spectrum)>) {
+fn animate_spectra(
+    mut query: Query<(&mut Path, &Spectrum)>,
+    axis: Res<FrequencyAxis>,
+    levels: Res<LevelAxis>,
+    appearance: Res<Appearance>,
+    analyzer: Res<AnalysisWorker>
+) {
+    let mut points = [0.0; PLOT_POINTS];
+
     for (mut path, spectrum) in query.iter_mut() {
         let mut path_builder = PathBuilder::new();
 
-        let width = PLOT_WIDTH / 2.0;
-        let samples = MAX_DFT_BIN;
+        let width = appearance.plot_width / 2.0;
+        let samples = PLOT_POINTS;
+        axis.resample(&spectrum.0, analyzer.bin_width(), &mut points);
 
-        for i in 0..samples {
-            let height = (spectrum.0[i] as f32)*100.0 + PLOT_Y_ZERO;
+        for (i, value) in points.iter().enumerate() {
+            let height = levels.position(*value).clamp(0.0, 1.0)*appearance.plot_height + appearance.plot_y_zero;
             path_builder.line_to(Vec2::new(-width+((i as f32) / (samples as f32))*width*2.0, height));
         }
         *path = path_builder.build();
@@ -0,0 +1,164 @@
+use bevy::prelude::*;
+use bevy_prototype_lyon::prelude::*;
+use live_spectrum::{loudest, AnalysisWorker, FrequencyAxis, LevelAxis, Peak, PeakFinder, SpectrumHistory, TraceKind};
+
+use crate::config::Appearance;
+use crate::waterfall::ViewMode;
+use crate::{latest_spectrum, AnalysisSettings, Label, Spectrum, SpectrumLine, OVERLAY_Z};
+
+// The most peaks that can be labelled at once on each trace
+pub const MAX_PEAKS: usize = 10;
+
+// The raw spectrum and one other trace
+const LABELLED_TRACES: usize = 2;
+
+// Which peaks get labelled on the plot
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct PeakLabels {
+    // How many of the loudest on each trace, up to `MAX_PEAKS`
+    pub count: usize,
+    // How loud they have to be, in dB
+    pub threshold: f32,
+    // The trace labelled alongside the raw spectrum, as long as it's shown
+    pub trace: TraceKind,
+    // Switched with P
+    pub shown: bool,
+}
+
+// Ticks above every labelled peak
+#[derive(Component)]
+pub struct PeakTicks;
+// The frequency above the peak at this index, loudest first on the raw spectrum and then the other trace
+#[derive(Component)]
+pub struct PeakTag(usize);
+// Every peak's frequency and level, in the top right of the plot
+#[derive(Component)]
+pub struct PeakList;
+
+// The filters that keep the text labels' queries apart
+type AnyLabel = Or<(With<PeakTag>, With<PeakList>)>;
+type ListFilter = (With<PeakList>, Without<PeakTag>, Without<PeakTicks>);
+
+pub fn setup_peaks(mut commands: Commands) {
+    // Colored in by `style_peaks`
+    commands.spawn_bundle(GeometryBuilder::build_as(
+        &PathBuilder::new().build(),
+        DrawMode::Stroke(StrokeMode::new(Color::NONE, 1.0)),
+        Transform::from_xyz(0.0, 0.0, OVERLAY_Z),
+    )).insert(PeakTicks);
+
+    let label = |horizontal, vertical| Text2dBundle {
+        text: Text::with_section("", TextStyle::default(), TextAlignment { vertical, horizontal }),
+        ..default()
+    };
+    for index in 0..LABELLED_TRACES * MAX_PEAKS {
+        commands.spawn_bundle(label(HorizontalAlign::Center, VerticalAlign::Bottom)).insert(PeakTag(index));
+    }
+    commands.spawn_bundle(label(HorizontalAlign::Right, VerticalAlign::Top)).insert(PeakList);
+}
+
+// Restyle the ticks and labels whenever the appearance changes
+pub fn style_peaks(
+    appearance: Res<Appearance>,
+    asset_server: Res<AssetServer>,
+    mut ticks: Query<&mut DrawMode, With<PeakTicks>>,
+    mut labels: Query<&mut Text, AnyLabel>
+) {
+    if !appearance.is_changed() {
+        return;
+    }
+
+    *ticks.single_mut() = DrawMode::Stroke(StrokeMode::new(appearance.scale_color, 1.0));
+    for mut text in labels.iter_mut() {
+        text.sections[0].style = TextStyle {
+            font: asset_server.load(&appearance.font),
+            font_size: 12.0,
+            color: appearance.scale_color,
+        };
+    }
+}
+
+// Show and hide the peak labels with P
+pub fn switch_peaks(keys: Res<Input<KeyCode>>, mut peaks: ResMut<PeakLabels>) {
+    if keys.just_pressed(KeyCode::P) {
+        peaks.shown = !peaks.shown;
+    }
+}
+
+// Pick the loudest peaks on the axis out of the latest raw spectrum and the peak trace, and label
+// each on the plot with its frequency and trace, and in the list with its level too
+// The other trace's labels sit a line higher, so they don't cover the raw ones where the peaks agree
+#[allow(clippy::too_many_arguments)]
+pub fn draw_peaks(
+    peaks: Res<PeakLabels>,
+    settings: Res<AnalysisSettings>,
+    view: Res<ViewMode>,
+    history: Res<SpectrumHistory>,
+    axis: Res<FrequencyAxis>,
+    levels: Res<LevelAxis>,
+    appearance: Res<Appearance>,
+    analyzer: Res<AnalysisWorker>,
+    lines: Query<(&Spectrum, &SpectrumLine)>,
+    mut ticks: Query<(&mut Path, &mut Visibility), With<PeakTicks>>,
+    mut tags: Query<(Label, &PeakTag), Without<PeakTicks>>,
+    mut list: Query<Label, ListFilter>
+) {
+    let traces: Vec<_> = lines.iter()
+        .filter(|(spectrum, line)| line.trace.kind() == peaks.trace && spectrum.0.len() == analyzer.bins())
+        .map(|(spectrum, _)| spectrum.0.clone())
+        .collect();
+    let raw = latest_spectrum(&history, &analyzer).map(|spectrum| (TraceKind::Raw, spectrum));
+    let other = (peaks.trace != TraceKind::Raw && !traces.is_empty()).then(|| (peaks.trace, loudest(&traces)));
+
+    let mut found: Vec<(TraceKind, Peak)> = Vec::new();
+    if peaks.shown && *view != ViewMode::Waterfall {
+        let finder = PeakFinder {
+            count: peaks.count.min(MAX_PEAKS),
+            threshold: peaks.threshold,
+            separation: 2 * settings.window.main_lobe_bins(),
+        };
+        let bin_width = analyzer.bin_width();
+        let bins = (axis.min / bin_width).ceil() as usize..(axis.max / bin_width).floor() as usize + 1;
+        for (kind, spectrum) in raw.into_iter().chain(other) {
+            found.extend(finder.find(&spectrum, bin_width, bins.clone()).into_iter().map(|peak| (kind, peak)));
+        }
+    }
+
+    // Where each peak goes on the plot
+    let points: Vec<_> = found.iter()
+        .map(|(kind, peak)| Vec2::new(
+            (axis.position(peak.freq) - 0.5) * appearance.plot_width,
+            levels.position(peak.level).clamp(0.0, 1.0) * appearance.plot_height + appearance.plot_y_zero
+                + if *kind == TraceKind::Raw { 0.0 } else { 14.0 },
+        ))
+        .collect();
+
+    let (mut path, mut visibility) = ticks.single_mut();
+    let mut path_builder = PathBuilder::new();
+    for point in &points {
+        path_builder.move_to(*point + Vec2::new(0.0, 4.0));
+        path_builder.line_to(*point + Vec2::new(0.0, 12.0));
+    }
+    *path = path_builder.build();
+    visibility.is_visible = !found.is_empty();
+
+    for ((mut text, mut transform, mut visibility), tag) in tags.iter_mut() {
+        visibility.is_visible = tag.0 < found.len();
+        if let (Some((kind, peak)), Some(point)) = (found.get(tag.0), points.get(tag.0)) {
+            text.sections[0].value = format!("{:.1} {}", peak.freq, kind);
+            transform.translation = (*point + Vec2::new(0.0, 14.0)).extend(OVERLAY_Z);
+        }
+    }
+
+    let (mut text, mut transform, mut visibility) = list.single_mut();
+    visibility.is_visible = !found.is_empty();
+    transform.translation = Vec3::new(
+        appearance.plot_width / 2.0 - 10.0,
+        appearance.plot_y_zero + appearance.plot_height - 10.0,
+        OVERLAY_Z,
+    );
+    text.sections[0].value = found.iter()
+        .map(|(kind, peak)| format!("{} {:.1} Hz {:.1} dB", kind, peak.freq, peak.level))
+        .collect::<Vec<_>>()
+        .join("\n");
+}
@@ -0,0 +1,93 @@
+use std::ops::Range;
+
+// A peak in a spectrum, with its frequency and level refined to between bins
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Peak {
+    pub freq: f32,
+    pub level: f32,
+}
+
+// Picks the loudest peaks out of a spectrum
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct PeakFinder {
+    // The most peaks to pick
+    pub count: usize,
+    // How loud a peak has to be, in dB
+    pub threshold: f32,
+    // How many bins apart peaks have to be, so the sidelobes and shoulders of a loud peak aren't
+    // taken for more of them. About twice `WindowFunction::main_lobe_bins` works
+    pub separation: usize,
+}
+
+impl PeakFinder {
+    // The loudest peaks of `spectrum`, whose bins are `bin_width` Hz apart, among `bins`, loudest first
+    pub fn find(&self, spectrum: &[f32], bin_width: f32, bins: Range<usize>) -> Vec<Peak> {
+        // Bins louder than the one below and at least as loud as the one above, which both have
+        // to be there to interpolate
+        let start = bins.start.max(1);
+        let end = bins.end.min(spectrum.len().saturating_sub(1));
+        let mut candidates: Vec<usize> = (start..end)
+            .filter(|&bin| {
+                let level = spectrum[bin];
+                level >= self.threshold && level > spectrum[bin - 1] && level >= spectrum[bin + 1]
+            })
+            .collect();
+        candidates.sort_by(|&a, &b| spectrum[b].total_cmp(&spectrum[a]));
+
+        let mut picked: Vec<usize> = Vec::new();
+        for bin in candidates {
+            if picked.len() == self.count {
+                break;
+            }
+            if picked.iter().all(|&other| bin.abs_diff(other) > self.separation) {
+                picked.push(bin);
+            }
+        }
+        picked.into_iter().map(|bin| interpolate(spectrum, bin, bin_width)).collect()
+    }
+}
+
+// Fit a parabola through `bin` and the bins either side and take its top. A window's main lobe is
+// close to a parabola in dB, so this gets within a small fraction of a bin
+pub(crate) fn interpolate(spectrum: &[f32], bin: usize, bin_width: f32) -> Peak {
+    let (below, at, above) = (spectrum[bin - 1], spectrum[bin], spectrum[bin + 1]);
+    let curvature = below - 2.0 * at + above;
+    // Flat tops stay where they are
+    let offset = if curvature < 0.0 { 0.5 * (below - above) / curvature } else { 0.0 };
+    Peak {
+        freq: (bin as f32 + offset) * bin_width,
+        level: at - 0.25 * (below - above) * offset,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::amplitude_to_db;
+    use crate::testing::{spectrum, BIN_WIDTH};
+
+    #[test]
+    fn peaks_land_between_bins() {
+        let (frame, bin_width) = spectrum(&[(100.3 * BIN_WIDTH, 0.5)]);
+        let finder = PeakFinder { count: 1, threshold: -100.0, separation: 4 };
+        let peaks = finder.find(&frame, bin_width, 0..frame.len());
+        assert_eq!(peaks.len(), 1);
+        assert!((peaks[0].freq / bin_width - 100.3).abs() < 0.05, "found bin {}", peaks[0].freq / bin_width);
+        // Closer than the 0.4 dB the bin itself reads low by
+        assert!((peaks[0].level - amplitude_to_db(0.5)).abs() < 0.2, "read {} dB", peaks[0].level);
+    }
+
+    #[test]
+    fn loudest_peaks_come_first_without_sidelobes() {
+        let (frame, bin_width) = spectrum(&[(200.0 * BIN_WIDTH, 0.1), (50.5 * BIN_WIDTH, 0.5), (400.0 * BIN_WIDTH, 0.001)]);
+        let finder = PeakFinder { count: 3, threshold: -50.0, separation: 4 };
+        let peaks = finder.find(&frame, bin_width, 0..frame.len());
+        let bins: Vec<_> = peaks.iter().map(|peak| (peak.freq / bin_width).round()).collect();
+        // The third tone is under the threshold
+        assert_eq!(bins, vec![51.0, 200.0]);
+
+        // Only looking above the loudest
+        let peaks = finder.find(&frame, bin_width, 100..frame.len());
+        assert_eq!(peaks.len(), 1);
+    }
+}
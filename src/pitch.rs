@@ -0,0 +1,170 @@
+use std::fmt;
+
+use crate::analyzer::MIN_DB;
+use crate::peaks::interpolate;
+
+// The notes in an octave, from C up, named with sharps
+const NOTE_NAMES: [&str; 12] = ["C", "C#", "D", "D#", "E", "F", "F#", "G", "G#", "A", "A#", "B"];
+// The MIDI number of A4, the note tunings are given by
+const A4_NUMBER: i32 = 69;
+
+// A note of the equal tempered scale, by its MIDI number (so 69 is A4 and 60 is middle C)
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Note(pub i32);
+
+impl Note {
+    // The nearest note to `freq`, with A4 at `a4` Hz, and how many cents `freq` is above it
+    pub fn nearest(freq: f32, a4: f32) -> (Note, f32) {
+        let semitones = 12.0 * (freq / a4).log2() + A4_NUMBER as f32;
+        let number = semitones.round();
+        (Note(number as i32), 100.0 * (semitones - number))
+    }
+
+    // Its frequency with A4 at `a4` Hz
+    pub fn freq(self, a4: f32) -> f32 {
+        a4 * 2f32.powf((self.0 - A4_NUMBER) as f32 / 12.0)
+    }
+
+    // "C#", without the octave
+    pub fn name(self) -> &'static str {
+        NOTE_NAMES[self.0.rem_euclid(12) as usize]
+    }
+
+    // Octaves start at C, so B3 is just under C4
+    pub fn octave(self) -> i32 {
+        self.0.div_euclid(12) - 1
+    }
+
+    // Sharps are the black keys of a piano
+    pub fn is_sharp(self) -> bool {
+        self.name().ends_with('#')
+    }
+}
+
+impl fmt::Display for Note {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}{}", self.name(), self.octave())
+    }
+}
+
+// Estimates the fundamental of a spectrum with a harmonic product spectrum: each candidate is
+// scored by the product of the levels at its harmonics (a sum, in dB), so the note whose
+// harmonics are all there wins even when one of them is louder than its fundamental
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct PitchDetector {
+    // How many harmonics, counting the fundamental, each candidate is scored by
+    pub harmonics: usize,
+    // The range the fundamental is looked for in, in Hz
+    pub min_freq: f32,
+    pub max_freq: f32,
+    // How loud the fundamental has to be, in dB
+    pub threshold: f32,
+}
+
+impl PitchDetector {
+    // The fundamental of `spectrum`, whose bins are `bin_width` Hz apart, if it has one
+    pub fn detect(&self, spectrum: &[f32], bin_width: f32) -> Option<f32> {
+        // Only peaks are candidates, which keeps the score of an octave below (whose harmonics
+        // include all of the real one's) from winning without a fundamental of its own
+        let start = ((self.min_freq / bin_width).ceil() as usize).max(1);
+        let end = ((self.max_freq / bin_width).floor() as usize + 1).min(spectrum.len().saturating_sub(1));
+        let fundamental = (start..end)
+            .filter(|&bin| {
+                let level = spectrum[bin];
+                level >= self.threshold && level > spectrum[bin - 1] && level >= spectrum[bin + 1]
+            })
+            .map(|bin| interpolate(spectrum, bin, bin_width).freq / bin_width)
+            .map(|bin| (bin, self.score(spectrum, bin)))
+            .max_by(|(_, a), (_, b)| a.total_cmp(b))?
+            .0;
+
+        // Higher harmonics pin the fundamental down more closely, the nth to within 1/n of the
+        // error of its own peak, so average them weighted by that
+        let (mut freqs, mut weights) = (0.0, 0.0);
+        for harmonic in 1..=self.harmonics {
+            if let Some(bin) = harmonic_peak(spectrum, fundamental * harmonic as f32) {
+                if spectrum[bin] >= self.threshold {
+                    freqs += interpolate(spectrum, bin, bin_width).freq;
+                    weights += harmonic as f32;
+                }
+            }
+        }
+        // None of them, the fundamental included, might be clear of the ends or the threshold
+        if weights == 0.0 {
+            return None;
+        }
+        Some(freqs / weights)
+    }
+
+    // The sum of the levels at each harmonic of `bin`, with missing ones as quiet as can be
+    fn score(&self, spectrum: &[f32], bin: f32) -> f32 {
+        (1..=self.harmonics)
+            .map(|harmonic| harmonic_peak(spectrum, bin * harmonic as f32).map_or(MIN_DB, |bin| spectrum[bin]))
+            .sum()
+    }
+}
+
+// The loudest bin within one of `bin`, which is fractional, as long as there are bins either side
+// of it to interpolate with
+fn harmonic_peak(spectrum: &[f32], bin: f32) -> Option<usize> {
+    let center = bin.round() as usize;
+    if center < 2 || center + 2 >= spectrum.len() {
+        return None;
+    }
+    (center - 1..=center + 1).max_by(|&a, &b| spectrum[a].total_cmp(&spectrum[b]))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::spectrum;
+
+    const DETECTOR: PitchDetector = PitchDetector { harmonics: 5, min_freq: 27.5, max_freq: 4200.0, threshold: -70.0 };
+
+    #[test]
+    fn fundamentals_beat_louder_harmonics() {
+        // A3 a few cents sharp, with its second and third harmonics louder than it
+        let freq = 221.0;
+        let tones: Vec<_> = [0.1, 0.4, 0.3, 0.1, 0.05].iter().enumerate()
+            .map(|(index, &amplitude)| (freq * (index + 1) as f32, amplitude))
+            .collect();
+        let (frame, bin_width) = spectrum(&tones);
+        let detected = DETECTOR.detect(&frame, bin_width).unwrap();
+        // Under a cent, where the bins are 11.7 Hz (80 cents) apart
+        assert!((detected - freq).abs() < 0.1, "detected {} Hz", detected);
+
+        let (note, cents) = Note::nearest(detected, 440.0);
+        assert_eq!(note.to_string(), "A3");
+        assert!((cents - 7.85).abs() < 1.0, "{} cents", cents);
+
+        // Nothing over the threshold, nothing detected
+        let (quiet, _) = spectrum(&[(freq, 0.0001)]);
+        assert_eq!(DETECTOR.detect(&quiet, bin_width), None);
+    }
+
+    #[test]
+    fn peaks_without_readable_harmonics_are_no_fundamental() {
+        // A peak in the second bin passes the threshold, but it's too close to the start to
+        // interpolate, and its harmonics run off the end
+        let spectrum = [-100.0, -10.0, -100.0, -100.0];
+        let detector = PitchDetector { min_freq: 0.0, ..DETECTOR };
+        assert_eq!(detector.detect(&spectrum, 10.0), None);
+    }
+
+    #[test]
+    fn notes_follow_the_reference() {
+        let (note, cents) = Note::nearest(261.63, 440.0);
+        assert_eq!((note, note.to_string()), (Note(60), "C4".to_string()));
+        assert!(cents.abs() < 0.1);
+        // B3 is in the octave below C4, and A#-2 is below MIDI's 0
+        assert_eq!(Note(59).to_string(), "B3");
+        assert_eq!(Note(-2).to_string(), "A#-2");
+        assert!(Note(-2).is_sharp() && !Note(59).is_sharp());
+
+        // With A4 at 432 Hz, 440 Hz is about 32 cents sharp of it
+        let (note, cents) = Note::nearest(440.0, 432.0);
+        assert_eq!(note, Note(69));
+        assert!((cents - 31.77).abs() < 0.1, "{} cents", cents);
+        assert!((Note(81).freq(432.0) - 864.0).abs() < 1e-3);
+    }
+}
@@ -0,0 +1,235 @@
+use std::fs::File;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use rtrb::{Consumer, Producer, RingBuffer};
+use symphonia::core::audio::SampleBuffer;
+use symphonia::core::codecs::{Decoder, DecoderOptions};
+use symphonia::core::errors::Error;
+use symphonia::core::formats::{FormatOptions, FormatReader};
+use symphonia::core::io::MediaSourceStream;
+use symphonia::core::meta::MetadataOptions;
+use symphonia::core::probe::Hint;
+
+use super::{pull_block, SampleSource, SourceError};
+
+// How much decoded audio can wait to be pulled before the decoding thread waits for room
+const BUFFER_SECONDS: f32 = 1.0;
+// How long the decoding thread waits before looking for room again
+const FULL_WAIT: Duration = Duration::from_millis(5);
+
+// Playback of an audio file (WAV, FLAC, OGG/Vorbis...), decoded on a background thread
+// In real-time mode it's streamed at the pace it would be recorded, so it behaves like a live input,
+// otherwise it's decoded as fast as it's pulled
+// Samples come out interleaved like the file has them, unless it doesn't say how many channels
+// it has, when they're mixed down to mono
+pub struct FileSource {
+    path: PathBuf,
+    sample_rate: u32,
+    channels: u16,
+    realtime: bool,
+    // Set to tell the decoding thread to give up
+    stop: Arc<AtomicBool>,
+    // Set by the decoding thread once the whole file has been sent
+    finished: Arc<AtomicBool>,
+    running: bool,
+    // Filled by the decoding thread, replaced every time playback starts
+    samples: Option<Consumer<f32>>,
+}
+
+impl FileSource {
+    // Check that we can decode the file at `path`, playback begins with `start`
+    pub fn new(path: &Path, realtime: bool) -> Result<Self, SourceError> {
+        let track = open_track(path)?;
+
+        Ok(FileSource {
+            path: path.to_path_buf(),
+            sample_rate: track.sample_rate,
+            channels: track.channels.unwrap_or(1) as u16,
+            realtime,
+            stop: Arc::new(AtomicBool::new(false)),
+            finished: Arc::new(AtomicBool::new(false)),
+            running: false,
+            samples: None,
+        })
+    }
+}
+
+impl SampleSource for FileSource {
+    // Play the file from the beginning
+    fn start(&mut self) -> Result<(), SourceError> {
+        if self.running {
+            return Ok(());
+        }
+
+        let track = open_track(&self.path)?;
+
+        self.stop = Arc::new(AtomicBool::new(false));
+        self.finished = Arc::new(AtomicBool::new(false));
+        let stop = self.stop.clone();
+        let finished = self.finished.clone();
+        let capacity = (BUFFER_SECONDS * self.sample_rate as f32) as usize * self.channels as usize;
+        let (producer, consumer) = RingBuffer::new(capacity);
+        self.samples = Some(consumer);
+        let realtime = self.realtime;
+        thread::spawn(move || {
+            decode_track(track, producer, stop, realtime);
+            finished.store(true, Ordering::Release);
+        });
+
+        self.running = true;
+        Ok(())
+    }
+
+    fn stop(&mut self) {
+        self.stop.store(true, Ordering::Relaxed);
+        self.running = false;
+    }
+
+    fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    fn channels(&self) -> u16 {
+        self.channels
+    }
+
+    fn pull_samples(&mut self, out: &mut Vec<f32>) {
+        if let Some(samples) = &mut self.samples {
+            pull_block(samples, out);
+        }
+    }
+
+    fn is_finished(&self) -> bool {
+        self.finished.load(Ordering::Acquire)
+    }
+}
+
+// An opened file, ready to decode the track we'll play
+struct Track {
+    format: Box<dyn FormatReader>,
+    decoder: Box<dyn Decoder>,
+    id: u32,
+    sample_rate: u32,
+    // Not every format says up front
+    channels: Option<usize>,
+}
+
+// Open the file and find the track we'll play, along with its decoder, sample rate and channels
+fn open_track(path: &Path) -> Result<Track, SourceError> {
+    let file = File::open(path).map_err(|err| SourceError::File(format!("{}: {}", path.display(), err)))?;
+    let stream = MediaSourceStream::new(Box::new(file), Default::default());
+
+    // The extension only helps the probe guess, it still looks at the file contents
+    let mut hint = Hint::new();
+    if let Some(extension) = path.extension().and_then(|ext| ext.to_str()) {
+        hint.with_extension(extension);
+    }
+
+    let probed = symphonia::default::get_probe()
+        .format(&hint, stream, &FormatOptions::default(), &MetadataOptions::default())
+        .map_err(|err| SourceError::File(format!("Unsupported audio file format: {}", err)))?;
+    let format = probed.format;
+
+    let track = format
+        .default_track()
+        .ok_or_else(|| SourceError::File("No audio track found in file".to_string()))?;
+    let id = track.id;
+    let sample_rate = track
+        .codec_params
+        .sample_rate
+        .ok_or_else(|| SourceError::File("Unknown sample rate".to_string()))?;
+    let channels = track.codec_params.channels.map(|channels| channels.count());
+
+    let decoder = symphonia::default::get_codecs()
+        .make(&track.codec_params, &DecoderOptions::default())
+        .map_err(|err| SourceError::File(format!("Unsupported audio codec: {}", err)))?;
+
+    Ok(Track {
+        format,
+        decoder,
+        id,
+        sample_rate,
+        channels,
+    })
+}
+
+// Decode the whole track into `samples`, sleeping as needed to keep to real time if `realtime` is set
+fn decode_track(mut track: Track, mut samples: Producer<f32>, stop: Arc<AtomicBool>, realtime: bool) {
+    let start = Instant::now();
+    let mut frames_sent: u64 = 0;
+    let mut sample_buf: Option<SampleBuffer<f32>> = None;
+    let mut mono = Vec::new();
+
+    // Any error here (including end of stream) means we're done with the file
+    while let Ok(packet) = track.format.next_packet() {
+        if stop.load(Ordering::Relaxed) {
+            return;
+        }
+        if packet.track_id() != track.id {
+            continue;
+        }
+
+        let decoded = match track.decoder.decode(&packet) {
+            Ok(decoded) => decoded,
+            // Corrupt packets can be skipped, anything else is fatal
+            Err(Error::DecodeError(_)) => continue,
+            Err(_) => break,
+        };
+
+        let spec = *decoded.spec();
+        let channels = spec.channels.count();
+        let needed = decoded.capacity() * channels;
+        if sample_buf.as_ref().map(|buf| buf.capacity()).unwrap_or(0) < needed {
+            sample_buf = Some(SampleBuffer::new(decoded.capacity() as u64, spec));
+        }
+        let buf = sample_buf.as_mut().unwrap();
+        buf.copy_interleaved_ref(decoded);
+
+        let block = if track.channels.is_some() {
+            buf.samples()
+        } else {
+            mono.clear();
+            mono.extend(buf.samples().chunks(channels).map(|frame| frame.iter().sum::<f32>() / channels as f32));
+            &mono
+        };
+        if !push_block(&mut samples, block, &stop) {
+            return;
+        }
+        frames_sent += (buf.samples().len() / channels) as u64;
+
+        if !realtime {
+            continue;
+        }
+
+        // Don't get ahead of real time, so the file plays back like a live input
+        let played = Duration::from_secs_f64(frames_sent as f64 / track.sample_rate as f64);
+        let elapsed = start.elapsed();
+        if played > elapsed {
+            thread::sleep(played - elapsed);
+        }
+    }
+}
+
+// Write all of `block`, waiting for room whenever the buffer is full
+// Gives up if we're told to stop, or nobody is pulling anymore, returning false
+fn push_block(samples: &mut Producer<f32>, mut block: &[f32], stop: &AtomicBool) -> bool {
+    while !block.is_empty() {
+        if stop.load(Ordering::Relaxed) || samples.is_abandoned() {
+            return false;
+        }
+        let room = block.len().min(samples.slots());
+        if room == 0 {
+            thread::sleep(FULL_WAIT);
+            continue;
+        }
+        if let Ok(chunk) = samples.write_chunk_uninit(room) {
+            chunk.fill_from_iter(block[..room].iter().copied());
+        }
+        block = &block[room..];
+    }
+    true
+}
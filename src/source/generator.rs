@@ -0,0 +1,365 @@
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use std::f64::consts::TAU;
+use std::str::FromStr;
+use std::time::Instant;
+
+use super::{SampleSource, SourceError};
+
+// Peak level of generated signals, leaving some headroom below full scale
+const AMPLITUDE: f32 = 0.5;
+
+// How many samples to hand out per pull when not keeping to real time
+const BLOCK_SIZE: usize = 4096;
+
+// A synthetic test signal
+#[derive(Clone, Debug, PartialEq)]
+pub enum Signal {
+    Sine(f32),
+    // Sweeps from `start` to `end` Hz over `duration` seconds, then starts over
+    Chirp { start: f32, end: f32, duration: f32, log: bool },
+    WhiteNoise,
+    PinkNoise,
+    // Sum of sines, each with its own amplitude relative to the others
+    Tones(Vec<Tone>),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Tone {
+    pub freq: f32,
+    pub amplitude: f32,
+}
+
+// Parse signal descriptions like
+//   sine:440
+//   chirp:20-20000:10      (linear sweep from 20 Hz to 20 kHz over 10 seconds)
+//   logchirp:20-20000:10   (logarithmic sweep)
+//   white
+//   pink
+//   tones:440,880@0.5,1000 (frequencies with optional relative amplitudes)
+impl FromStr for Signal {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (kind, args) = match s.split_once(':') {
+            Some((kind, args)) => (kind, Some(args)),
+            None => (s, None),
+        };
+
+        match (kind, args) {
+            ("sine", Some(freq)) => Ok(Signal::Sine(parse_freq(freq)?)),
+            ("chirp", Some(args)) | ("logchirp", Some(args)) => {
+                let (range, duration) = args
+                    .split_once(':')
+                    .ok_or_else(|| format!("Expected <start>-<end>:<seconds>, got \"{}\"", args))?;
+                let (start, end) = range
+                    .split_once('-')
+                    .ok_or_else(|| format!("Expected <start>-<end>, got \"{}\"", range))?;
+                let duration: f32 = duration
+                    .parse()
+                    .map_err(|_| format!("Invalid sweep duration \"{}\"", duration))?;
+                if duration <= 0.0 {
+                    return Err("Sweep duration must be positive".to_string());
+                }
+                Ok(Signal::Chirp {
+                    start: parse_freq(start)?,
+                    end: parse_freq(end)?,
+                    duration,
+                    log: kind == "logchirp",
+                })
+            }
+            ("white", None) => Ok(Signal::WhiteNoise),
+            ("pink", None) => Ok(Signal::PinkNoise),
+            ("tones", Some(tones)) => tones
+                .split(',')
+                .map(|tone| match tone.split_once('@') {
+                    Some((freq, amplitude)) => Ok(Tone {
+                        freq: parse_freq(freq)?,
+                        amplitude: amplitude
+                            .parse()
+                            .map_err(|_| format!("Invalid amplitude \"{}\"", amplitude))?,
+                    }),
+                    None => Ok(Tone { freq: parse_freq(tone)?, amplitude: 1.0 }),
+                })
+                .collect::<Result<Vec<_>, _>>()
+                .map(Signal::Tones),
+            _ => Err(format!(
+                "Unknown signal \"{}\", expected sine:<hz>, chirp:<hz>-<hz>:<s>, logchirp:<hz>-<hz>:<s>, white, pink or tones:<hz>[@<amp>],...",
+                s
+            )),
+        }
+    }
+}
+
+impl Signal {
+    // Every frequency the signal has a sine at, or sweeps to
+    fn freqs(&self) -> Vec<f32> {
+        match self {
+            Signal::Sine(freq) => vec![*freq],
+            Signal::Chirp { start, end, .. } => vec![*start, *end],
+            Signal::WhiteNoise | Signal::PinkNoise => Vec::new(),
+            Signal::Tones(tones) => tones.iter().map(|tone| tone.freq).collect(),
+        }
+    }
+}
+
+fn parse_freq(freq: &str) -> Result<f32, String> {
+    match freq.parse::<f32>() {
+        Ok(freq) if freq > 0.0 => Ok(freq),
+        _ => Err(format!("Invalid frequency \"{}\"", freq)),
+    }
+}
+
+// Generates a test signal on the fly
+// In real-time mode samples come as fast as a live input would produce them,
+// otherwise every pull gets another block
+pub struct GeneratorSource {
+    signal: Signal,
+    sample_rate: u32,
+    realtime: bool,
+    started: Option<Instant>,
+    // Samples generated since we were started
+    position: u64,
+    // One phase accumulator per oscillator, in radians
+    phases: Vec<f64>,
+    rng: StdRng,
+    // State of the pink noise filter
+    pink: [f32; 7],
+}
+
+impl GeneratorSource {
+    // Frequencies have to be below half of `sample_rate`, or they'd come out as aliases
+    pub fn new(signal: Signal, sample_rate: u32, realtime: bool) -> Result<Self, SourceError> {
+        let nyquist = sample_rate as f32 / 2.0;
+        if let Some(freq) = signal.freqs().into_iter().find(|&freq| freq >= nyquist) {
+            return Err(SourceError::Signal(format!(
+                "{} Hz can't be generated at {} Hz, it has to be below {} Hz",
+                freq, sample_rate, nyquist
+            )));
+        }
+
+        let oscillators = match &signal {
+            Signal::Tones(tones) => tones.len(),
+            _ => 1,
+        };
+
+        Ok(GeneratorSource {
+            signal,
+            sample_rate,
+            realtime,
+            started: None,
+            position: 0,
+            phases: vec![0.0; oscillators],
+            rng: StdRng::from_entropy(),
+            pink: [0.0; 7],
+        })
+    }
+
+    fn next_sample(&mut self) -> f32 {
+        let sample_rate = self.sample_rate as f64;
+        let t = self.position as f64 / sample_rate;
+        self.position += 1;
+
+        match &self.signal {
+            Signal::Sine(freq) => AMPLITUDE * advance(&mut self.phases[0], *freq as f64, sample_rate),
+            Signal::Chirp { start, end, duration, log } => {
+                let (start, end, duration) = (*start as f64, *end as f64, *duration as f64);
+                let progress = (t % duration) / duration;
+                let freq = if *log {
+                    start * (end / start).powf(progress)
+                } else {
+                    start + (end - start) * progress
+                };
+                AMPLITUDE * advance(&mut self.phases[0], freq, sample_rate)
+            }
+            Signal::WhiteNoise => AMPLITUDE * self.rng.gen_range(-1.0..1.0),
+            Signal::PinkNoise => {
+                // Paul Kellet's refined pink noise filter, roughly -3 dB/octave over the audio band
+                let white: f32 = self.rng.gen_range(-1.0..1.0);
+                let b = &mut self.pink;
+                b[0] = 0.99886 * b[0] + white * 0.0555179;
+                b[1] = 0.99332 * b[1] + white * 0.0750759;
+                b[2] = 0.96900 * b[2] + white * 0.153852;
+                b[3] = 0.86650 * b[3] + white * 0.3104856;
+                b[4] = 0.55000 * b[4] + white * 0.5329522;
+                b[5] = -0.7616 * b[5] - white * 0.0168980;
+                let pink = b[0] + b[1] + b[2] + b[3] + b[4] + b[5] + b[6] + white * 0.5362;
+                b[6] = white * 0.115926;
+                // The filter has a gain of about 9
+                AMPLITUDE * pink * 0.11
+            }
+            Signal::Tones(tones) => {
+                // Scale so the sum can't clip
+                let total: f32 = tones.iter().map(|tone| tone.amplitude.abs()).sum();
+                let mut sum = 0.0;
+                for (tone, phase) in tones.iter().zip(self.phases.iter_mut()) {
+                    sum += tone.amplitude * advance(phase, tone.freq as f64, sample_rate);
+                }
+                AMPLITUDE * sum / total.max(f32::EPSILON)
+            }
+        }
+    }
+}
+
+// Output the sine of an oscillator's phase, then step it along at `freq`
+fn advance(phase: &mut f64, freq: f64, sample_rate: f64) -> f32 {
+    let value = phase.sin() as f32;
+    *phase = (*phase + TAU * freq / sample_rate) % TAU;
+    value
+}
+
+impl SampleSource for GeneratorSource {
+    fn start(&mut self) -> Result<(), SourceError> {
+        if self.started.is_none() {
+            self.started = Some(Instant::now());
+            self.position = 0;
+        }
+        Ok(())
+    }
+
+    fn stop(&mut self) {
+        self.started = None;
+    }
+
+    fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    fn channels(&self) -> u16 {
+        1
+    }
+
+    fn pull_samples(&mut self, out: &mut Vec<f32>) {
+        if let Some(started) = self.started {
+            // Catch up to where a real-time source would be by now
+            let due = if self.realtime {
+                (started.elapsed().as_secs_f64() * self.sample_rate as f64) as u64
+            } else {
+                self.position + BLOCK_SIZE as u64
+            };
+            while self.position < due {
+                let sample = self.next_sample();
+                out.push(sample);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SAMPLE_RATE: u32 = 48000;
+
+    // The first `len` samples of `signal`
+    fn generate(signal: Signal, len: usize) -> Vec<f32> {
+        let mut source = GeneratorSource::new(signal, SAMPLE_RATE, false).unwrap();
+        source.start().unwrap();
+        let mut samples = Vec::new();
+        while samples.len() < len {
+            source.pull_samples(&mut samples);
+        }
+        samples.truncate(len);
+        samples
+    }
+
+    // Where the signal crosses 0 on the way up, in samples, interpolated between them
+    fn rising_crossings(samples: &[f32]) -> Vec<f64> {
+        samples.windows(2).enumerate()
+            .filter(|(_, pair)| pair[0] < 0.0 && pair[1] >= 0.0)
+            .map(|(index, pair)| index as f64 + (pair[0] / (pair[0] - pair[1])) as f64)
+            .collect()
+    }
+
+    // The frequency going by the time between two rising crossings
+    fn freq_between(from: f64, to: f64, periods: usize) -> f64 {
+        periods as f64 * SAMPLE_RATE as f64 / (to - from)
+    }
+
+    fn peak(samples: &[f32]) -> f32 {
+        samples.iter().fold(0.0, |peak, sample| peak.max(sample.abs()))
+    }
+
+    #[test]
+    fn every_form_parses() {
+        assert_eq!("sine:440".parse(), Ok(Signal::Sine(440.0)));
+        assert_eq!(
+            "chirp:20-20000:10".parse(),
+            Ok(Signal::Chirp { start: 20.0, end: 20000.0, duration: 10.0, log: false })
+        );
+        assert_eq!(
+            "logchirp:20-20000:0.5".parse(),
+            Ok(Signal::Chirp { start: 20.0, end: 20000.0, duration: 0.5, log: true })
+        );
+        assert_eq!("white".parse(), Ok(Signal::WhiteNoise));
+        assert_eq!("pink".parse(), Ok(Signal::PinkNoise));
+        assert_eq!(
+            "tones:440,880@0.5,1000".parse(),
+            Ok(Signal::Tones(vec![
+                Tone { freq: 440.0, amplitude: 1.0 },
+                Tone { freq: 880.0, amplitude: 0.5 },
+                Tone { freq: 1000.0, amplitude: 1.0 },
+            ]))
+        );
+    }
+
+    #[test]
+    fn malformed_signals_are_rejected() {
+        for signal in [
+            "", "sine", "sine:", "sine:abc", "sine:0", "sine:-440", "square:440", "white:1", "pink:2",
+            "chirp:20-20000", "chirp:20:10", "chirp:20-:10", "chirp:20-20000:0", "chirp:20-20000:x",
+            "logchirp:0-1000:1", "tones", "tones:", "tones:440,", "tones:440@", "tones:440@loud",
+        ] {
+            assert!(signal.parse::<Signal>().is_err(), "\"{}\" parsed", signal);
+        }
+    }
+
+    #[test]
+    fn sines_have_their_frequency_and_amplitude() {
+        let samples = generate(Signal::Sine(1000.0), SAMPLE_RATE as usize);
+        let crossings = rising_crossings(&samples);
+        assert_eq!(crossings.len(), 999);
+        let freq = freq_between(crossings[0], crossings[crossings.len() - 1], crossings.len() - 1);
+        assert!((freq - 1000.0).abs() < 0.01, "{} Hz", freq);
+        assert!((peak(&samples) - AMPLITUDE).abs() < 1e-3, "peak of {}", peak(&samples));
+    }
+
+    #[test]
+    fn tones_are_scaled_not_to_clip() {
+        // Two tones in phase at the same frequency add up to four times the first
+        let samples = generate("tones:1000,1000@3".parse().unwrap(), 4800);
+        assert!((peak(&samples) - AMPLITUDE).abs() < 1e-3, "peak of {}", peak(&samples));
+
+        let samples = generate("tones:100,150@2,225@4".parse().unwrap(), SAMPLE_RATE as usize);
+        assert!(peak(&samples) <= AMPLITUDE + 1e-6, "peak of {}", peak(&samples));
+    }
+
+    #[test]
+    fn chirps_reach_the_end_at_the_end() {
+        for log in [false, true] {
+            // A single sweep from 100 Hz to 1 kHz over a second
+            let signal = Signal::Chirp { start: 100.0, end: 1000.0, duration: 1.0, log };
+            let expected = |seconds: f64| if log { 100.0 * 10f64.powf(seconds) } else { 100.0 + 900.0 * seconds };
+            let crossings = rising_crossings(&generate(signal, SAMPLE_RATE as usize));
+
+            // Each period against the frequency halfway through it, at either end of the sweep
+            for pair in [&crossings[..2], &crossings[crossings.len() - 2..]] {
+                let freq = freq_between(pair[0], pair[1], 1);
+                let expected = expected((pair[0] + pair[1]) / 2.0 / SAMPLE_RATE as f64);
+                assert!((freq / expected - 1.0).abs() < 0.01, "{} Hz, expected {} Hz", freq, expected);
+            }
+            // The last of them within a period of the end
+            assert!(crossings[crossings.len() - 1] > 0.999 * SAMPLE_RATE as f64);
+        }
+    }
+
+    #[test]
+    fn frequencies_past_nyquist_are_rejected() {
+        for signal in ["sine:24000", "chirp:20-30000:1", "logchirp:30000-20:1", "tones:440,25000@0.1"] {
+            let signal: Signal = signal.parse().unwrap();
+            assert!(GeneratorSource::new(signal.clone(), SAMPLE_RATE, false).is_err(), "{:?} generated", signal);
+        }
+        assert!(GeneratorSource::new(Signal::Sine(23999.0), SAMPLE_RATE, false).is_ok());
+        assert!(GeneratorSource::new(Signal::WhiteNoise, SAMPLE_RATE, false).is_ok());
+    }
+}
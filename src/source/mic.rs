@@ -0,0 +1,408 @@
+use cpal::traits::{DeviceTrait, HostTrait, StreamTrait};
+use cpal::{Sample, SampleFormat};
+use rtrb::{Consumer, Producer, RingBuffer};
+use std::fmt;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use super::{pull_block, SampleSource, SourceError};
+
+// Tells input devices apart, even when the same name turns up under several hosts
+#[derive(Clone, Debug, PartialEq)]
+pub struct DeviceId {
+    pub host: cpal::HostId,
+    pub name: String,
+}
+
+impl fmt::Display for DeviceId {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} ({})", self.name, self.host.name())
+    }
+}
+
+// A capture device found by `input_devices`
+pub struct InputDevice {
+    pub id: DeviceId,
+    // Whether this is the default input of the default host
+    pub is_default: bool,
+    device: cpal::Device,
+}
+
+impl InputDevice {
+    // Every range of configs the device supports, like "2 channels, 44100-96000 Hz, I16"
+    pub fn configs(&self) -> Vec<String> {
+        let configs = match self.device.supported_input_configs() {
+            Ok(configs) => configs,
+            Err(err) => return vec![format!("couldn't get configs: {}", err)],
+        };
+        configs
+            .map(|config| {
+                let (min, max) = (config.min_sample_rate().0, config.max_sample_rate().0);
+                let rates = if min == max { format!("{} Hz", min) } else { format!("{}-{} Hz", min, max) };
+                format!("{} channels, {}, {:?}", config.channels(), rates, config.sample_format())
+            })
+            .collect()
+    }
+}
+
+// Every input device of every host we can use, in a stable order so they can be picked by index
+// Hosts or devices that fail to open are left out
+pub fn input_devices() -> Vec<InputDevice> {
+    let default_host = cpal::default_host().id();
+    let default_name = cpal::default_host()
+        .default_input_device()
+        .and_then(|device| device.name().ok());
+
+    let mut devices = Vec::new();
+    for host_id in cpal::available_hosts() {
+        let host = match cpal::host_from_id(host_id) {
+            Ok(host) => host,
+            Err(_) => continue,
+        };
+        let host_devices = match host.input_devices() {
+            Ok(host_devices) => host_devices,
+            Err(_) => continue,
+        };
+        for device in host_devices {
+            if let Ok(name) = device.name() {
+                let is_default = host_id == default_host && Some(&name) == default_name.as_ref();
+                devices.push(InputDevice {
+                    id: DeviceId { host: host_id, name },
+                    is_default,
+                    device,
+                });
+            }
+        }
+    }
+    devices
+}
+
+// Which input device to use: the default, one by its index in `input_devices`, or one by name
+// A name can be part of the device's name as long as only one device matches it
+#[derive(Clone, Debug, PartialEq)]
+pub enum DeviceSelector {
+    Default,
+    Index(usize),
+    Name(String),
+}
+
+impl FromStr for DeviceSelector {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s.is_empty() {
+            return Err("Empty device name".to_string());
+        }
+        Ok(match s.parse::<usize>() {
+            Ok(index) => DeviceSelector::Index(index),
+            Err(_) if s == "default" => DeviceSelector::Default,
+            Err(_) => DeviceSelector::Name(s.to_string()),
+        })
+    }
+}
+
+impl DeviceSelector {
+    // Pick our device out of `devices`
+    pub fn find(&self, devices: Vec<InputDevice>) -> Result<InputDevice, SourceError> {
+        let found = match self {
+            DeviceSelector::Default => devices.into_iter().find(|device| device.is_default),
+            DeviceSelector::Index(index) => devices.into_iter().nth(*index),
+            DeviceSelector::Name(name) => {
+                let wanted = name.to_lowercase();
+                let (exact, partial): (Vec<_>, Vec<_>) = devices
+                    .into_iter()
+                    .filter(|device| device.id.name.to_lowercase().contains(&wanted))
+                    .partition(|device| device.id.name.to_lowercase() == wanted);
+
+                if exact.len() + partial.len() > 1 && exact.len() != 1 {
+                    let matches: Vec<_> = exact.iter().chain(&partial).map(|device| device.id.to_string()).collect();
+                    return Err(SourceError::Device(format!(
+                        "Several devices match \"{}\": {}",
+                        name,
+                        matches.join(", ")
+                    )));
+                }
+                exact.into_iter().chain(partial).next()
+            }
+        };
+
+        found.ok_or_else(|| match self {
+            DeviceSelector::Default => SourceError::Device("No microphone found".to_string()),
+            DeviceSelector::Index(index) => SourceError::Device(format!("No input device number {}", index)),
+            DeviceSelector::Name(name) => SourceError::Device(format!("No input device called \"{}\"", name)),
+        })
+    }
+}
+
+// How long a running stream can go without sending anything before we give up on it
+// Some hosts just stop calling back when a device goes away, rather than reporting an error
+const STALL_TIMEOUT: Duration = Duration::from_secs(2);
+
+// How much audio the buffer between the audio callback and us holds, in seconds
+// If we fall behind far enough to fill it, the callback drops whatever doesn't fit (the newest
+// samples) until we catch up, and counts them
+const BUFFER_SECONDS: f32 = 0.5;
+
+// Live input from a cpal capture device
+pub struct MicSource {
+    id: DeviceId,
+    device: cpal::Device,
+    config: cpal::SupportedStreamConfig,
+    stream: Option<cpal::Stream>,
+    // Filled with whole blocks by the audio callback, without locking or allocating, and emptied
+    // by `pull_samples`. Each stream gets a new one
+    samples: Option<Consumer<f32>>,
+    // How many samples the callback had no room for
+    dropped: Arc<AtomicU64>,
+    // Errors from the stream are rare enough for a channel
+    error_tx: Sender<cpal::StreamError>,
+    error_rx: Receiver<cpal::StreamError>,
+    // When we last pulled anything from the running stream
+    last_samples: Instant,
+}
+
+impl MicSource {
+    // Use the default input device of the default host
+    pub fn new() -> Result<Self, SourceError> {
+        MicSource::with_device(&DeviceSelector::Default)
+    }
+
+    pub fn with_device(selector: &DeviceSelector) -> Result<Self, SourceError> {
+        MicSource::from_device(selector.find(input_devices())?)
+    }
+
+    // Use `device` with the best config it supports, see `best_config`
+    pub fn from_device(device: InputDevice) -> Result<Self, SourceError> {
+        let config = best_config(&device.device)
+            .map_err(|err| SourceError::Device(format!("No supported config for {}: {}", device.id, err)))?;
+
+        let (error_tx, error_rx) = channel();
+
+        Ok(MicSource {
+            id: device.id,
+            device: device.device,
+            config,
+            stream: None,
+            samples: None,
+            dropped: Arc::new(AtomicU64::new(0)),
+            error_tx,
+            error_rx,
+            last_samples: Instant::now(),
+        })
+    }
+
+    pub fn device_id(&self) -> &DeviceId {
+        &self.id
+    }
+
+    // Whether the callback has sent anything we haven't pulled yet. If we haven't pulled for a while
+    // because the frame loop was held up, the stream is still fine as long as this is
+    fn has_samples(&self) -> bool {
+        self.samples.as_ref().is_some_and(|samples| samples.slots() > 0)
+    }
+}
+
+impl SampleSource for MicSource {
+    fn start(&mut self) -> Result<(), SourceError> {
+        if self.stream.is_some() {
+            return Ok(());
+        }
+
+        let config = self.config.config();
+        let capacity = (config.sample_rate.0 as f32 * config.channels as f32 * BUFFER_SECONDS) as usize;
+        let (producer, consumer) = RingBuffer::new(capacity);
+        let transport = Transport {
+            samples: producer,
+            dropped: self.dropped.clone(),
+            errors: self.error_tx.clone(),
+        };
+        let stream = match self.config.sample_format() {
+            SampleFormat::F32 => build_stream::<f32>(&self.device, &config, transport),
+            SampleFormat::I16 => build_stream::<i16>(&self.device, &config, transport),
+            SampleFormat::U16 => build_stream::<u16>(&self.device, &config, transport),
+        }
+        .map_err(|err| SourceError::Stream(err.to_string()))?;
+        stream.play().map_err(|err| SourceError::Stream(err.to_string()))?;
+
+        self.stream = Some(stream);
+        self.samples = Some(consumer);
+        self.last_samples = Instant::now();
+        Ok(())
+    }
+
+    fn stop(&mut self) {
+        // Dropping the stream closes it
+        self.stream = None;
+    }
+
+    fn sample_rate(&self) -> u32 {
+        self.config.sample_rate().0
+    }
+
+    fn channels(&self) -> u16 {
+        self.config.channels()
+    }
+
+    fn pull_samples(&mut self, out: &mut Vec<f32>) {
+        if let Some(samples) = &mut self.samples {
+            if pull_block(samples, out) > 0 {
+                self.last_samples = Instant::now();
+            }
+        }
+    }
+
+    fn dropped_samples(&self) -> u64 {
+        self.dropped.load(Ordering::Relaxed)
+    }
+
+    // The device going away, or the stream going quiet, stops the stream
+    fn take_error(&mut self) -> Option<SourceError> {
+        let mut errors: Vec<_> = self.error_rx.try_iter().collect();
+        let lost = if errors.iter().any(|err| matches!(err, cpal::StreamError::DeviceNotAvailable)) {
+            "the device is no longer available"
+        } else if self.stream.is_some() && self.last_samples.elapsed() > STALL_TIMEOUT && !self.has_samples() {
+            "the device stopped sending samples"
+        } else {
+            // Anything else may pass, so report the latest and carry on
+            return errors.pop().map(|err| SourceError::Stream(err.to_string()));
+        };
+        self.stop();
+        Some(SourceError::Device(lost.to_string()))
+    }
+}
+
+// What the audio callback hands its samples and errors over with
+struct Transport {
+    samples: Producer<f32>,
+    dropped: Arc<AtomicU64>,
+    errors: Sender<cpal::StreamError>,
+}
+
+// A stream handing every block of samples, converted to f32 between -1 and 1, over through `transport`
+fn build_stream<T: Sample>(
+    device: &cpal::Device,
+    config: &cpal::StreamConfig,
+    transport: Transport,
+) -> Result<cpal::Stream, cpal::BuildStreamError> {
+    let Transport { mut samples, dropped, errors } = transport;
+    device.build_input_stream(
+        config,
+        move |data: &[T], _: &cpal::InputCallbackInfo| push_block(&mut samples, data, &dropped),
+        move |err| {
+            // The receiving end lives as long as the stream does, so this can't fail
+            let _ = errors.send(err);
+        },
+    )
+}
+
+// Write as much of `data` as there's room for, counting the rest in `dropped`
+// This runs in the audio callback, so it mustn't block or allocate
+fn push_block<T: Sample>(samples: &mut Producer<f32>, data: &[T], dropped: &AtomicU64) {
+    let room = data.len().min(samples.slots());
+    if let Ok(chunk) = samples.write_chunk_uninit(room) {
+        chunk.fill_from_iter(data.iter().map(|val| val.to_f32()));
+    }
+    if room < data.len() {
+        dropped.fetch_add((data.len() - room) as u64, Ordering::Relaxed);
+    }
+}
+
+// Where the default config isn't the best one, it's usually because it's in an integer format
+// while f32 is there too. So stick to the default rate and channels when we can, and pick the
+// most precise format at those
+fn best_config(device: &cpal::Device) -> Result<cpal::SupportedStreamConfig, String> {
+    let default = device.default_input_config().ok();
+    let ranges: Vec<_> = match device.supported_input_configs() {
+        Ok(ranges) => ranges.collect(),
+        Err(err) => return default.ok_or_else(|| err.to_string()),
+    };
+
+    let rate = default.as_ref().map(|config| config.sample_rate().0).unwrap_or(PREFERRED_SAMPLE_RATE);
+    let channels = default.as_ref().map(|config| config.channels());
+    let choices: Vec<_> = ranges
+        .iter()
+        .map(|range| ConfigChoice {
+            rate: rate.clamp(range.min_sample_rate().0, range.max_sample_rate().0),
+            channels: range.channels(),
+            format: range.sample_format(),
+        })
+        .collect();
+
+    match pick_config(&choices, rate, channels) {
+        Some(best) => Ok(ranges[best].clone().with_sample_rate(cpal::SampleRate(choices[best].rate))),
+        None => default.ok_or_else(|| "the device doesn't offer any".to_string()),
+    }
+}
+
+// Used when the device has no default config to go by
+const PREFERRED_SAMPLE_RATE: u32 = 48000;
+
+// What one of the device's supported config ranges would run at
+#[derive(Clone, Copy, Debug)]
+struct ConfigChoice {
+    rate: u32,
+    channels: u16,
+    format: SampleFormat,
+}
+
+// The index of the best of `choices` for running at `rate` with `channels`
+// Getting the rate matters most, then the format, then the channels, then a higher rate
+fn pick_config(choices: &[ConfigChoice], rate: u32, channels: Option<u16>) -> Option<usize> {
+    let precision = |format: SampleFormat| match format {
+        SampleFormat::F32 => 2,
+        SampleFormat::I16 => 1,
+        SampleFormat::U16 => 0,
+    };
+    (0..choices.len()).max_by_key(|&index| {
+        let choice = &choices[index];
+        (choice.rate == rate, precision(choice.format), Some(choice.channels) == channels, choice.rate)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn selectors_parse_indices_and_names() {
+        assert_eq!("2".parse(), Ok(DeviceSelector::Index(2)));
+        assert_eq!("default".parse(), Ok(DeviceSelector::Default));
+        assert_eq!("USB Audio".parse(), Ok(DeviceSelector::Name("USB Audio".to_string())));
+        assert!("".parse::<DeviceSelector>().is_err());
+    }
+
+    #[test]
+    fn full_buffers_drop_and_count_new_samples() {
+        let (mut producer, mut consumer) = RingBuffer::new(4);
+        let dropped = AtomicU64::new(0);
+
+        push_block(&mut producer, &[0.5f32, -0.5, 0.25], &dropped);
+        push_block(&mut producer, &[i16::MAX, 0, i16::MIN], &dropped);
+        assert_eq!(dropped.load(Ordering::Relaxed), 2);
+
+        let mut out = Vec::new();
+        assert_eq!(pull_block(&mut consumer, &mut out), 4);
+        assert_eq!(&out[..3], &[0.5, -0.5, 0.25]);
+        assert!((out[3] - 1.0).abs() < 1e-3);
+        assert_eq!(pull_block(&mut consumer, &mut out), 0);
+    }
+
+    #[test]
+    fn configs_keep_the_rate_and_prefer_f32() {
+        let choice = |rate, channels, format| ConfigChoice { rate, channels, format };
+
+        let choices = [
+            choice(44100, 2, SampleFormat::I16),
+            choice(44100, 1, SampleFormat::F32),
+            choice(96000, 2, SampleFormat::F32),
+        ];
+        assert_eq!(pick_config(&choices, 44100, Some(2)), Some(1));
+
+        let choices = [choice(48000, 2, SampleFormat::I16), choice(96000, 2, SampleFormat::U16)];
+        assert_eq!(pick_config(&choices, 44100, Some(2)), Some(0));
+        assert_eq!(pick_config(&[], 44100, None), None);
+    }
+}
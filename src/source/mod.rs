@@ -0,0 +1,82 @@
+use rtrb::Consumer;
+use std::error::Error;
+use std::fmt;
+
+mod file;
+mod generator;
+mod mic;
+
+pub use file::FileSource;
+pub use generator::{GeneratorSource, Signal};
+pub use mic::{input_devices, DeviceId, DeviceSelector, InputDevice, MicSource};
+
+// Anything that can produce audio samples for us to analyse
+// Sources hand over samples through `pull_samples`, so the STFT code doesn't care where they came from
+pub trait SampleSource {
+    // Begin producing samples. Calling this on a started source does nothing
+    fn start(&mut self) -> Result<(), SourceError>;
+
+    // Stop producing samples, the source may be started again later
+    fn stop(&mut self);
+
+    fn sample_rate(&self) -> u32;
+
+    fn channels(&self) -> u16;
+
+    // Append every sample produced since the last call to `out`, interleaved by channel
+    fn pull_samples(&mut self, out: &mut Vec<f32>);
+
+    // Whether the source has run out of samples for good, like at the end of a file
+    // Samples produced before finishing can still be pulled
+    fn is_finished(&self) -> bool {
+        false
+    }
+
+    // How many samples have been lost so far because they weren't pulled in time
+    fn dropped_samples(&self) -> u64 {
+        0
+    }
+
+    // Whatever went wrong since the last call, for sources that can fail while running
+    // A `SourceError::Device` means the source has stopped for good, and needs replacing
+    fn take_error(&mut self) -> Option<SourceError> {
+        None
+    }
+}
+
+#[derive(Debug)]
+pub enum SourceError {
+    // No usable device, or the device has no usable config
+    Device(String),
+    // The device exists but we couldn't get a stream going
+    Stream(String),
+    // The file couldn't be read or decoded
+    File(String),
+    // The test signal can't be generated at the sample rate
+    Signal(String),
+}
+
+impl fmt::Display for SourceError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SourceError::Device(msg) => write!(f, "audio device error: {}", msg),
+            SourceError::Stream(msg) => write!(f, "audio stream error: {}", msg),
+            SourceError::File(msg) => write!(f, "audio file error: {}", msg),
+            SourceError::Signal(msg) => write!(f, "test signal error: {}", msg),
+        }
+    }
+}
+
+impl Error for SourceError {}
+
+// Append everything waiting in `samples` to `out`, returning how many there were
+fn pull_block(samples: &mut Consumer<f32>, out: &mut Vec<f32>) -> usize {
+    let waiting = samples.slots();
+    if let Ok(chunk) = samples.read_chunk(waiting) {
+        let (first, second) = chunk.as_slices();
+        out.extend_from_slice(first);
+        out.extend_from_slice(second);
+        chunk.commit_all();
+    }
+    waiting
+}
@@ -0,0 +1,23 @@
+// Fixtures shared by the library's tests
+
+use crate::{SpectrumAnalyzer, WindowFunction};
+
+// How many Hz apart the bins of `spectrum` are
+pub const BIN_WIDTH: f32 = 48000.0 / 4096.0;
+
+// One frame of `tones` (frequency in Hz, amplitude) through a 4096 point Hann window at 48 kHz,
+// and how many Hz apart its bins are
+pub fn spectrum(tones: &[(f32, f32)]) -> (Vec<f32>, f32) {
+    let mut analyzer = SpectrumAnalyzer::new(4096, 4096, 48000, WindowFunction::Hann);
+    let samples: Vec<f32> = (0..4096)
+        .map(|i| {
+            tones.iter().map(|&(freq, amplitude)| {
+                amplitude * (2.0 * std::f32::consts::PI * freq * i as f32 / 48000.0).sin()
+            }).sum()
+        })
+        .collect();
+    analyzer.push_samples(&samples);
+    let mut frame = vec![0.0; analyzer.bins()];
+    assert!(analyzer.next_frame(&mut frame));
+    (frame, analyzer.bin_width())
+}
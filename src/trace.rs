@@ -0,0 +1,291 @@
+use std::collections::VecDeque;
+use std::fmt;
+use std::mem;
+use std::str::FromStr;
+
+use crate::analyzer::MIN_DB;
+use crate::envelope::{update_envelope, Ballistics};
+
+// The ways a signal's spectrum can be followed from column to column, each drawn as a trace of its own
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TraceKind {
+    // The latest column as it is
+    Raw,
+    // Jumps up to peaks and falls back slowly, as its `Ballistics` say
+    Envelope,
+    // Power averaged with a time constant, see `Averaging`
+    Exponential,
+    // Power averaged over the last so many columns, each counting the same
+    Linear,
+    // The loudest each bin has been since the last reset
+    MaxHold,
+    // And the quietest
+    MinHold,
+}
+
+impl TraceKind {
+    pub const ALL: [TraceKind; 6] = [
+        TraceKind::Raw,
+        TraceKind::Envelope,
+        TraceKind::Exponential,
+        TraceKind::Linear,
+        TraceKind::MaxHold,
+        TraceKind::MinHold,
+    ];
+
+    fn index(self) -> usize {
+        TraceKind::ALL.iter().position(|&kind| kind == self).unwrap()
+    }
+}
+
+impl FromStr for TraceKind {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match TraceKind::ALL.iter().find(|kind| kind.to_string() == s) {
+            Some(kind) => Ok(*kind),
+            None => Err(format!(
+                "Unknown trace \"{}\", expected raw, envelope, exponential, linear, max-hold or min-hold",
+                s
+            )),
+        }
+    }
+}
+
+impl fmt::Display for TraceKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            TraceKind::Raw => "raw",
+            TraceKind::Envelope => "envelope",
+            TraceKind::Exponential => "exponential",
+            TraceKind::Linear => "linear",
+            TraceKind::MaxHold => "max-hold",
+            TraceKind::MinHold => "min-hold",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+// Which traces are shown, written as a comma separated list like "envelope,max-hold", or "none"
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct TraceSet([bool; TraceKind::ALL.len()]);
+
+impl TraceSet {
+    pub fn contains(&self, kind: TraceKind) -> bool {
+        self.0[kind.index()]
+    }
+
+    pub fn toggle(&mut self, kind: TraceKind) {
+        self.0[kind.index()] ^= true;
+    }
+
+    // In `TraceKind::ALL` order
+    pub fn iter(&self) -> impl Iterator<Item = TraceKind> + '_ {
+        TraceKind::ALL.into_iter().filter(|&kind| self.contains(kind))
+    }
+}
+
+impl FromIterator<TraceKind> for TraceSet {
+    fn from_iter<I: IntoIterator<Item = TraceKind>>(kinds: I) -> Self {
+        let mut set = TraceSet::default();
+        for kind in kinds {
+            set.0[kind.index()] = true;
+        }
+        set
+    }
+}
+
+impl FromStr for TraceSet {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s == "none" {
+            return Ok(TraceSet::default());
+        }
+        s.split(',').map(|kind| kind.trim().parse()).collect()
+    }
+}
+
+impl fmt::Display for TraceSet {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let kinds: Vec<_> = self.iter().map(|kind| kind.to_string()).collect();
+        if kinds.is_empty() {
+            write!(f, "none")
+        } else {
+            write!(f, "{}", kinds.join(","))
+        }
+    }
+}
+
+// How the averaging traces average
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Averaging {
+    // Time constant of the exponential average, in seconds
+    pub time: f32,
+    // How many columns the linear average covers
+    pub frames: usize,
+}
+
+// Whatever a trace needs to remember between columns, besides the spectrum it shows
+// Averages are worked out on power rather than dB, so they're true power (RMS) averages
+pub struct Trace {
+    kind: TraceKind,
+    // The exponential average
+    power: Vec<f32>,
+    // The powers of the columns the linear average covers, oldest first, and their sum
+    recent: VecDeque<Vec<f32>>,
+    sum: Vec<f64>,
+    // Set until the first column after a reset, which the trace starts over from
+    fresh: bool,
+}
+
+impl Trace {
+    pub fn new(kind: TraceKind) -> Self {
+        Trace {
+            kind,
+            power: Vec::new(),
+            recent: VecDeque::new(),
+            sum: Vec::new(),
+            fresh: true,
+        }
+    }
+
+    pub fn kind(&self) -> TraceKind {
+        self.kind
+    }
+
+    // Forget every column so far, like when clearing a max hold
+    pub fn reset(&mut self) {
+        self.fresh = true;
+    }
+
+    // Work the next column `raw`, `interval` seconds after the last one, into `spectrum`
+    pub fn update(
+        &mut self,
+        spectrum: &mut [f32],
+        raw: &[f32],
+        ballistics: &Ballistics,
+        averaging: &Averaging,
+        interval: f32,
+    ) {
+        let fresh = mem::replace(&mut self.fresh, false);
+        match self.kind {
+            TraceKind::Raw => spectrum.copy_from_slice(raw),
+            TraceKind::Envelope if fresh => spectrum.copy_from_slice(raw),
+            TraceKind::Envelope => update_envelope(spectrum, raw, ballistics, interval),
+            TraceKind::Exponential => {
+                let keep = if fresh || averaging.time <= 0.0 { 0.0 } else { (-interval / averaging.time).exp() };
+                self.power.resize(raw.len(), 0.0);
+                for ((power, raw), out) in self.power.iter_mut().zip(raw).zip(spectrum) {
+                    *power = *power*keep + db_to_power(*raw)*(1.0-keep);
+                    *out = power_to_db(*power);
+                }
+            }
+            TraceKind::Linear => {
+                if fresh {
+                    self.recent.clear();
+                    self.sum = vec![0.0; raw.len()];
+                }
+                // Drop the oldest columns, keeping the last buffer to reuse
+                let frames = averaging.frames.max(1);
+                let mut powers = Vec::new();
+                while self.recent.len() >= frames {
+                    powers = self.recent.pop_front().unwrap();
+                    for (sum, old) in self.sum.iter_mut().zip(&powers) {
+                        *sum -= *old as f64;
+                    }
+                }
+
+                powers.clear();
+                powers.extend(raw.iter().map(|raw| db_to_power(*raw)));
+                for (sum, power) in self.sum.iter_mut().zip(&powers) {
+                    *sum += *power as f64;
+                }
+                self.recent.push_back(powers);
+
+                let count = self.recent.len() as f64;
+                for (out, sum) in spectrum.iter_mut().zip(&self.sum) {
+                    // Subtracting can leave a hair below 0 for bins with nothing in them
+                    *out = power_to_db((sum / count).max(0.0) as f32);
+                }
+            }
+            TraceKind::MaxHold | TraceKind::MinHold if fresh => spectrum.copy_from_slice(raw),
+            TraceKind::MaxHold => {
+                for (out, raw) in spectrum.iter_mut().zip(raw) {
+                    *out = out.max(*raw);
+                }
+            }
+            TraceKind::MinHold => {
+                for (out, raw) in spectrum.iter_mut().zip(raw) {
+                    *out = out.min(*raw);
+                }
+            }
+        }
+    }
+}
+
+fn db_to_power(db: f32) -> f32 {
+    10f32.powf(db / 10.0)
+}
+
+fn power_to_db(power: f32) -> f32 {
+    (10.0 * power.log10()).max(MIN_DB)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BALLISTICS: Ballistics = Ballistics { attack: 0.0, release: 0.3 };
+
+    // Run `columns` through a new trace of `kind`, one bin each
+    fn follow(kind: TraceKind, averaging: &Averaging, columns: &[f32]) -> f32 {
+        let mut trace = Trace::new(kind);
+        let mut spectrum = [MIN_DB];
+        for column in columns {
+            trace.update(&mut spectrum, &[*column], &BALLISTICS, averaging, 0.1);
+        }
+        spectrum[0]
+    }
+
+    #[test]
+    fn averages_are_power_averages() {
+        let averaging = Averaging { time: 1.0, frames: 2 };
+        // Half the power of -10 dB is -13 dB, nowhere near the -55 dB halfway down to -100
+        let linear = follow(TraceKind::Linear, &averaging, &[-40.0, -100.0, -10.0]);
+        assert!((linear - (-13.0103)).abs() < 1e-3);
+
+        // Settles on a steady level whatever it started from
+        let steady = [vec![-100.0], vec![-20.0; 200]].concat();
+        assert!((follow(TraceKind::Exponential, &averaging, &steady) - (-20.0)).abs() < 1e-3);
+        // And gets a little over halfway there (in power) after one time constant
+        let one_time_constant = [vec![-100.0], vec![-20.0; 10]].concat();
+        let power = db_to_power(follow(TraceKind::Exponential, &averaging, &one_time_constant)) / db_to_power(-20.0);
+        assert!((power - (1.0 - (-1.0f32).exp())).abs() < 1e-3);
+    }
+
+    #[test]
+    fn holds_keep_the_extremes_until_reset() {
+        let averaging = Averaging { time: 1.0, frames: 4 };
+        let columns = [-50.0, -20.0, -70.0, -40.0];
+        assert_eq!(follow(TraceKind::MaxHold, &averaging, &columns), -20.0);
+        assert_eq!(follow(TraceKind::MinHold, &averaging, &columns), -70.0);
+
+        let mut trace = Trace::new(TraceKind::MaxHold);
+        let mut spectrum = [MIN_DB];
+        trace.update(&mut spectrum, &[-20.0], &BALLISTICS, &averaging, 0.1);
+        trace.reset();
+        trace.update(&mut spectrum, &[-60.0], &BALLISTICS, &averaging, 0.1);
+        assert_eq!(spectrum[0], -60.0);
+    }
+
+    #[test]
+    fn sets_parse_from_lists() {
+        let set: TraceSet = "envelope, max-hold".parse().unwrap();
+        assert!(set.contains(TraceKind::Envelope) && set.contains(TraceKind::MaxHold));
+        assert_eq!(set.iter().count(), 2);
+        assert_eq!(set.to_string(), "envelope,max-hold");
+        assert_eq!("none".parse::<TraceSet>().unwrap().iter().count(), 0);
+        assert!("envelope,peak".parse::<TraceSet>().is_err());
+    }
+}
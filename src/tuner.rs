@@ -0,0 +1,153 @@
+use bevy::prelude::*;
+use bevy_prototype_lyon::prelude::*;
+use live_spectrum::{AnalysisWorker, Note, PitchDetector, SpectrumHistory};
+
+use crate::config::Appearance;
+use crate::{latest_spectrum, OVERLAY_Z};
+
+// Fundamentals from A0 to C8, the range of a piano, judged by their first five harmonics, as long as
+// they're clear of the noise
+const DETECTOR: PitchDetector = PitchDetector { harmonics: 5, min_freq: 27.5, max_freq: 4186.0, threshold: -60.0 };
+
+// How far the meter goes either side of a note, in cents, and how wide it is
+const METER_CENTS: f32 = 50.0;
+const METER_WIDTH: f32 = 200.0;
+
+// The tuner panel at the top of the plot
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Tuner {
+    // The frequency of A4 the notes are tuned to, in Hz
+    pub a4: f32,
+    // Switched with T
+    pub shown: bool,
+}
+
+// The note, how far off it is, and the frequency
+#[derive(Component)]
+pub struct TunerLabel;
+// The scale of the meter, with a tick every 10 cents
+#[derive(Component)]
+pub struct TunerScale;
+// The needle showing how far off the note is
+#[derive(Component)]
+pub struct TunerNeedle;
+
+// The filters that keep the queries for each part apart
+type NeedleFilter = (With<TunerNeedle>, Without<TunerScale>);
+type LabelFilter = (With<TunerLabel>, Without<TunerScale>, Without<TunerNeedle>);
+
+pub fn setup_tuner(mut commands: Commands) {
+    // Colored in by `style_tuner`
+    let lines = || GeometryBuilder::build_as(
+        &PathBuilder::new().build(),
+        DrawMode::Stroke(StrokeMode::new(Color::NONE, 1.0)),
+        Transform::from_xyz(0.0, 0.0, OVERLAY_Z),
+    );
+    commands.spawn_bundle(lines()).insert(TunerScale);
+    commands.spawn_bundle(lines()).insert(TunerNeedle);
+
+    // The note goes in the first section, bigger than the rest
+    commands.spawn_bundle(Text2dBundle {
+        text: Text {
+            sections: vec![TextSection::default(), TextSection::default()],
+            alignment: TextAlignment {
+                vertical: VerticalAlign::Top,
+                horizontal: HorizontalAlign::Center,
+            },
+        },
+        ..default()
+    }).insert(TunerLabel);
+}
+
+// Restyle and place the tuner whenever the appearance changes
+pub fn style_tuner(
+    appearance: Res<Appearance>,
+    asset_server: Res<AssetServer>,
+    mut scale: Query<(&mut Path, &mut DrawMode), With<TunerScale>>,
+    mut needle: Query<&mut DrawMode, (With<TunerNeedle>, Without<TunerScale>)>,
+    mut label: Query<(&mut Text, &mut Transform), With<TunerLabel>>
+) {
+    if !appearance.is_changed() {
+        return;
+    }
+
+    let meter_y = meter_y(&appearance);
+    let (mut path, mut draw_mode) = scale.single_mut();
+    let mut path_builder = PathBuilder::new();
+    path_builder.move_to(Vec2::new(-METER_WIDTH / 2.0, meter_y));
+    path_builder.line_to(Vec2::new(METER_WIDTH / 2.0, meter_y));
+    for tick in -5..=5 {
+        let x = tick as f32 * 10.0 / METER_CENTS * METER_WIDTH / 2.0;
+        let height = if tick == 0 { 8.0 } else { 4.0 };
+        path_builder.move_to(Vec2::new(x, meter_y - height));
+        path_builder.line_to(Vec2::new(x, meter_y + height));
+    }
+    *path = path_builder.build();
+    *draw_mode = DrawMode::Stroke(StrokeMode::new(appearance.scale_color, 1.0));
+    *needle.single_mut() = DrawMode::Stroke(StrokeMode::new(appearance.marker_color, 2.0));
+
+    let (mut text, mut transform) = label.single_mut();
+    for (section, font_size) in text.sections.iter_mut().zip([24.0, 12.0]) {
+        section.style = TextStyle {
+            font: asset_server.load(&appearance.font),
+            font_size,
+            color: appearance.scale_color,
+        };
+    }
+    transform.translation = Vec3::new(0.0, appearance.plot_y_zero + appearance.plot_height - 10.0, OVERLAY_Z);
+}
+
+// Show and hide the tuner with T
+pub fn switch_tuner(keys: Res<Input<KeyCode>>, mut tuner: ResMut<Tuner>) {
+    if keys.just_pressed(KeyCode::T) {
+        tuner.shown = !tuner.shown;
+    }
+}
+
+// Find the fundamental of the latest spectrum, and show the nearest note and how far off it is
+pub fn update_tuner(
+    tuner: Res<Tuner>,
+    history: Res<SpectrumHistory>,
+    analyzer: Res<AnalysisWorker>,
+    appearance: Res<Appearance>,
+    mut scale: Query<&mut Visibility, With<TunerScale>>,
+    mut needle: Query<(&mut Path, &mut Visibility), NeedleFilter>,
+    mut label: Query<(&mut Text, &mut Visibility), LabelFilter>
+) {
+    let (mut text, mut label_visibility) = label.single_mut();
+    let (mut path, mut needle_visibility) = needle.single_mut();
+    scale.single_mut().is_visible = tuner.shown;
+    label_visibility.is_visible = tuner.shown;
+    needle_visibility.is_visible = false;
+    if !tuner.shown {
+        return;
+    }
+
+    let freq = latest_spectrum(&history, &analyzer)
+        .and_then(|spectrum| DETECTOR.detect(&spectrum, analyzer.bin_width()));
+    let freq = match freq {
+        Some(freq) => freq,
+        None => {
+            text.sections[0].value = "-".to_string();
+            text.sections[1].value = format!("\nA4 = {:.1} Hz", tuner.a4);
+            return;
+        }
+    };
+
+    let (note, cents) = Note::nearest(freq, tuner.a4);
+    text.sections[0].value = note.to_string();
+    text.sections[1].value = format!("\n{:+.1} cents, {:.1} Hz", cents, freq);
+
+    let x = cents / METER_CENTS * METER_WIDTH / 2.0;
+    let meter_y = meter_y(&appearance);
+    let mut path_builder = PathBuilder::new();
+    path_builder.move_to(Vec2::new(x, meter_y - 10.0));
+    path_builder.line_to(Vec2::new(x, meter_y + 10.0));
+    *path = path_builder.build();
+    needle_visibility.is_visible = true;
+}
+
+// Under the label
+fn meter_y(appearance: &Appearance) -> f32 {
+    appearance.plot_y_zero + appearance.plot_height - 70.0
+}
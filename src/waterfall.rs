@@ -0,0 +1,341 @@
+use bevy::prelude::*;
+use bevy::render::render_resource::{Extent3d, TextureDimension, TextureFormat};
+use bevy::sprite::Anchor;
+use live_spectrum::{loudest, AnalysisWorker, FrequencyAxis, LevelAxis, SpectrumHistory, MIN_DB};
+use std::collections::VecDeque;
+use std::str::FromStr;
+
+use crate::config::Appearance;
+use crate::{AnalysisSettings, LevelScale, Spectrum, PLOT_POINTS};
+
+// How many columns of history the waterfall keeps
+const WATERFALL_ROWS: usize = 256;
+
+// How tall the waterfall is when it's shown below the line plot
+const WATERFALL_BELOW_HEIGHT: f32 = 230.0;
+
+// Which views of the spectrum to show
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ViewMode {
+    Line,
+    Waterfall,
+    Both,
+}
+
+impl ViewMode {
+    fn next(self) -> Self {
+        match self {
+            ViewMode::Line => ViewMode::Waterfall,
+            ViewMode::Waterfall => ViewMode::Both,
+            ViewMode::Both => ViewMode::Line,
+        }
+    }
+}
+
+impl FromStr for ViewMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "line" => Ok(ViewMode::Line),
+            "waterfall" => Ok(ViewMode::Waterfall),
+            "both" => Ok(ViewMode::Both),
+            _ => Err(format!("Unknown view \"{}\", expected line, waterfall or both", s)),
+        }
+    }
+}
+
+// How waterfall magnitudes are turned into colors
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ColorMap {
+    Viridis,
+    Magma,
+    Inferno,
+    Grayscale,
+}
+
+// Evenly spaced samples of each map, from low to high, to interpolate between
+const VIRIDIS: [[u8; 3]; 9] = [
+    [68, 1, 84], [71, 44, 122], [59, 81, 139], [44, 113, 142], [33, 144, 141],
+    [39, 173, 129], [92, 200, 99], [170, 220, 50], [253, 231, 37],
+];
+const MAGMA: [[u8; 3]; 9] = [
+    [0, 0, 4], [28, 16, 68], [79, 18, 123], [129, 37, 129], [181, 54, 122],
+    [229, 80, 100], [251, 135, 97], [254, 194, 135], [252, 253, 191],
+];
+const INFERNO: [[u8; 3]; 9] = [
+    [0, 0, 4], [31, 12, 72], [85, 15, 109], [136, 34, 106], [186, 54, 85],
+    [227, 89, 51], [249, 140, 10], [249, 201, 50], [252, 255, 164],
+];
+const GRAYSCALE: [[u8; 3]; 2] = [[0, 0, 0], [255, 255, 255]];
+
+impl ColorMap {
+    fn next(self) -> Self {
+        match self {
+            ColorMap::Viridis => ColorMap::Magma,
+            ColorMap::Magma => ColorMap::Inferno,
+            ColorMap::Inferno => ColorMap::Grayscale,
+            ColorMap::Grayscale => ColorMap::Viridis,
+        }
+    }
+
+    // RGBA color for `level`, which goes from 0 to 1
+    fn color(self, level: f32) -> [u8; 4] {
+        let stops: &[[u8; 3]] = match self {
+            ColorMap::Viridis => &VIRIDIS,
+            ColorMap::Magma => &MAGMA,
+            ColorMap::Inferno => &INFERNO,
+            ColorMap::Grayscale => &GRAYSCALE,
+        };
+
+        let pos = level.clamp(0.0, 1.0) * (stops.len() - 1) as f32;
+        let low = (pos.floor() as usize).min(stops.len() - 2);
+        let frac = pos - low as f32;
+
+        let mut color = [255; 4];
+        for c in 0..3 {
+            let (a, b) = (stops[low][c] as f32, stops[low + 1][c] as f32);
+            color[c] = (a + (b - a) * frac).round() as u8;
+        }
+        color
+    }
+}
+
+impl FromStr for ColorMap {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "viridis" => Ok(ColorMap::Viridis),
+            "magma" => Ok(ColorMap::Magma),
+            "inferno" => Ok(ColorMap::Inferno),
+            "grayscale" => Ok(ColorMap::Grayscale),
+            _ => Err(format!("Unknown color map \"{}\", expected viridis, magma, inferno or grayscale", s)),
+        }
+    }
+}
+
+// The scrolling spectrogram: frequency across, time going down with the newest column on top
+#[derive(Component)]
+pub struct Waterfall {
+    image: Handle<Image>,
+    // The columns on display, newest first, kept so we can redraw everything when the
+    // color map or either axis changes
+    history: VecDeque<Vec<f32>>,
+}
+
+pub fn setup_waterfall(mut commands: Commands, mut images: ResMut<Assets<Image>>, colormap: Res<ColorMap>) {
+    let image = Image::new_fill(
+        Extent3d {
+            width: PLOT_POINTS as u32,
+            height: WATERFALL_ROWS as u32,
+            depth_or_array_layers: 1,
+        },
+        TextureDimension::D2,
+        &colormap.color(0.0),
+        TextureFormat::Rgba8UnormSrgb,
+    );
+    let image = images.add(image);
+
+    commands.spawn_bundle(SpriteBundle {
+        sprite: Sprite {
+            anchor: Anchor::TopCenter,
+            ..default()
+        },
+        texture: image.clone(),
+        ..default()
+    }).insert(Waterfall {
+        image,
+        history: VecDeque::with_capacity(WATERFALL_ROWS),
+    });
+}
+
+// Scroll every new spectrum column into the waterfall
+#[allow(clippy::too_many_arguments)]
+pub fn update_waterfall(
+    history: Res<SpectrumHistory>,
+    mut seen: Local<u64>,
+    mut query: Query<&mut Waterfall>,
+    mut images: ResMut<Assets<Image>>,
+    colormap: Res<ColorMap>,
+    axis: Res<FrequencyAxis>,
+    levels: Res<LevelAxis>,
+    analyzer: Res<AnalysisWorker>,
+    settings: Res<AnalysisSettings>,
+) {
+    let mut waterfall = query.single_mut();
+    let mut new_rows = 0;
+
+    // Columns from before the analysis settings or the input changed don't fit with the new ones, so start again
+    let restarted = settings.is_changed()
+        || matches!(waterfall.history.front(), Some(column) if column.len() != analyzer.bins());
+    if restarted {
+        waterfall.history.clear();
+    }
+
+    let (frames, added) = history.since(*seen);
+    *seen = added;
+    for frame in frames {
+        // One might still be there from the old analyzer
+        if frame.spectra[0].len() != analyzer.bins() {
+            continue;
+        }
+        if waterfall.history.len() == WATERFALL_ROWS {
+            waterfall.history.pop_back();
+        }
+        // With several signals, each bin shows the loudest of them
+        waterfall.history.push_front(loudest(&frame.spectra));
+        new_rows += 1;
+    }
+
+    // Only draw the new rows, unless the way we draw them changed
+    let rows = if restarted || colormap.is_changed() || axis.is_changed() || levels.is_changed() {
+        WATERFALL_ROWS
+    } else {
+        new_rows.min(WATERFALL_ROWS)
+    };
+    if rows == 0 {
+        return;
+    }
+
+    if let Some(image) = images.get_mut(&waterfall.image) {
+        let mut points = [0.0; PLOT_POINTS];
+        scroll_in(&mut image.data, PLOT_POINTS * 4, &waterfall.history, rows, |column, pixels| {
+            match column {
+                Some(column) => axis.resample(column, analyzer.bin_width(), &mut points),
+                None => points.fill(MIN_DB),
+            }
+            for (pixel, value) in pixels.chunks_exact_mut(4).zip(&points) {
+                pixel.copy_from_slice(&colormap.color(levels.position(*value)));
+            }
+        });
+    }
+}
+
+// Move the rows of `image`, `row_bytes` long, down by `rows`, then fill in the ones that frees up at
+// the top from the newest `rows` columns of `history` (newest first) with `paint`. Rows past the
+// end of the history are painted from None
+fn scroll_in(
+    image: &mut [u8],
+    row_bytes: usize,
+    history: &VecDeque<Vec<f32>>,
+    rows: usize,
+    mut paint: impl FnMut(Option<&Vec<f32>>, &mut [u8]),
+) {
+    let total = image.len() / row_bytes;
+    let rows = rows.min(total);
+    image.copy_within(..(total - rows) * row_bytes, rows * row_bytes);
+    for (row, pixels) in image.chunks_exact_mut(row_bytes).take(rows).enumerate() {
+        paint(history.get(row), pixels);
+    }
+}
+
+// Switch views with V and color maps with C
+pub fn switch_view(keys: Res<Input<KeyCode>>, mut view: ResMut<ViewMode>, mut colormap: ResMut<ColorMap>) {
+    if keys.just_pressed(KeyCode::V) {
+        *view = view.next();
+    }
+    if keys.just_pressed(KeyCode::C) {
+        *colormap = colormap.next();
+    }
+}
+
+// Everything that belongs to the line plot
+type LinePlotFilter = (Or<(With<Spectrum>, With<LevelScale>)>, Without<Waterfall>);
+
+// Show, hide and place the line plot (with its level scale) and waterfall according to the view mode
+pub fn apply_view(
+    view: Res<ViewMode>,
+    appearance: Res<Appearance>,
+    mut waterfall_query: Query<(&mut Sprite, &mut Transform, &mut Visibility), With<Waterfall>>,
+    mut line_query: Query<&mut Visibility, LinePlotFilter>,
+) {
+    if !view.is_changed() && !appearance.is_changed() {
+        return;
+    }
+
+    let (mut sprite, mut transform, mut visibility) = waterfall_query.single_mut();
+    let (top, height) = match *view {
+        // In place of the line plot, above the frequency scale
+        ViewMode::Waterfall => {
+            let top = appearance.plot_y_zero + appearance.plot_height + 20.0;
+            (top, top - appearance.plot_y_zero - 15.0)
+        }
+        // Below the line plot, under the frequency scale
+        _ => (appearance.plot_y_zero - 65.0, WATERFALL_BELOW_HEIGHT),
+    };
+    sprite.custom_size = Some(Vec2::new(appearance.plot_width, height));
+    transform.translation = Vec3::new(0.0, top, 0.0);
+    visibility.is_visible = *view != ViewMode::Line;
+
+    for mut visibility in line_query.iter_mut() {
+        visibility.is_visible = *view != ViewMode::Waterfall;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MAPS: [ColorMap; 4] = [ColorMap::Viridis, ColorMap::Magma, ColorMap::Inferno, ColorMap::Grayscale];
+
+    fn stops(map: ColorMap) -> &'static [[u8; 3]] {
+        match map {
+            ColorMap::Viridis => &VIRIDIS,
+            ColorMap::Magma => &MAGMA,
+            ColorMap::Inferno => &INFERNO,
+            ColorMap::Grayscale => &GRAYSCALE,
+        }
+    }
+
+    // Relative luminance, from linear light
+    fn luminance([r, g, b, _]: [u8; 4]) -> f32 {
+        let linear = |c: u8| {
+            let c = c as f32 / 255.0;
+            if c <= 0.04045 { c / 12.92 } else { ((c + 0.055) / 1.055).powf(2.4) }
+        };
+        0.2126 * linear(r) + 0.7152 * linear(g) + 0.0722 * linear(b)
+    }
+
+    #[test]
+    fn maps_run_from_end_to_end_and_clamp() {
+        for map in MAPS {
+            let (first, last) = (stops(map)[0], stops(map)[stops(map).len() - 1]);
+            assert_eq!(map.color(0.0), [first[0], first[1], first[2], 255], "{:?}", map);
+            assert_eq!(map.color(1.0), [last[0], last[1], last[2], 255], "{:?}", map);
+            assert_eq!(map.color(-0.5), map.color(0.0), "{:?}", map);
+            assert_eq!(map.color(3.0), map.color(1.0), "{:?}", map);
+        }
+    }
+
+    #[test]
+    fn maps_get_brighter_all_the_way_up() {
+        for map in MAPS {
+            let colors: Vec<_> = (0..=256).map(|step| luminance(map.color(step as f32 / 256.0))).collect();
+            // Rounding to 8 bits can take a hair off between neighbouring steps
+            for (index, pair) in colors.windows(2).enumerate() {
+                assert!(pair[1] >= pair[0] - 0.001, "{:?} gets darker at {}/256", map, index + 1);
+            }
+        }
+    }
+
+    #[test]
+    fn newest_columns_go_on_top() {
+        // One pixel wide, four rows tall, each pixel holding its column's only value
+        let paint = |column: Option<&Vec<f32>>, pixels: &mut [u8]| pixels.fill(column.map_or(0, |column| column[0] as u8));
+        let mut image = vec![0; 4 * 4];
+        let mut history = VecDeque::new();
+        for value in 1..=3 {
+            history.push_front(vec![value as f32]);
+            scroll_in(&mut image, 4, &history, 1, paint);
+        }
+        let rows: Vec<_> = image.chunks(4).map(|row| row[0]).collect();
+        assert_eq!(rows, [3, 2, 1, 0]);
+
+        // Redrawing everything paints rows past the history from nothing
+        history.pop_back();
+        scroll_in(&mut image, 4, &history, 10, paint);
+        let rows: Vec<_> = image.chunks(4).map(|row| row[0]).collect();
+        assert_eq!(rows, [3, 2, 0, 0]);
+    }
+}
@@ -0,0 +1,125 @@
+use std::f32::consts::PI;
+use std::fmt;
+use std::str::FromStr;
+
+// The window applied to each block of samples before its FFT
+// Trades frequency resolution (how narrow a peak is) against leakage (how far it spreads) and
+// amplitude accuracy between bins, from rectangular (narrowest, leakiest) to flat-top (widest,
+// but reads the right level wherever the frequency falls)
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum WindowFunction {
+    Rectangular,
+    Hann,
+    Hamming,
+    Blackman,
+    BlackmanHarris,
+    FlatTop,
+}
+
+impl WindowFunction {
+    pub fn next(self) -> Self {
+        match self {
+            WindowFunction::Rectangular => WindowFunction::Hann,
+            WindowFunction::Hann => WindowFunction::Hamming,
+            WindowFunction::Hamming => WindowFunction::Blackman,
+            WindowFunction::Blackman => WindowFunction::BlackmanHarris,
+            WindowFunction::BlackmanHarris => WindowFunction::FlatTop,
+            WindowFunction::FlatTop => WindowFunction::Rectangular,
+        }
+    }
+
+    // All of these are sums of cosines, a0 - a1 cos(x) + a2 cos(2x) - ...
+    fn cosine_terms(self) -> &'static [f32] {
+        match self {
+            WindowFunction::Rectangular => &[1.0],
+            WindowFunction::Hann => &[0.5, 0.5],
+            WindowFunction::Hamming => &[0.54, 0.46],
+            WindowFunction::Blackman => &[0.42, 0.5, 0.08],
+            WindowFunction::BlackmanHarris => &[0.35875, 0.48829, 0.14128, 0.01168],
+            WindowFunction::FlatTop => &[0.215_578_95, 0.416_631_58, 0.277_263_16, 0.083_578_95, 0.006_947_368],
+        }
+    }
+
+    // How many bins a sine's main lobe reaches either side of its own, one for each cosine term
+    pub fn main_lobe_bins(self) -> usize {
+        self.cosine_terms().len()
+    }
+
+    // The window `len` samples long, symmetric so the first and last samples match
+    pub fn coefficients(self, len: usize) -> Vec<f32> {
+        let terms = self.cosine_terms();
+        let denominator = (len.max(2) - 1) as f32;
+
+        (0..len)
+            .map(|n| {
+                let x = 2.0 * PI * n as f32 / denominator;
+                terms.iter().enumerate().fold(0.0, |sum, (k, a)| {
+                    let sign = if k % 2 == 0 { 1.0 } else { -1.0 };
+                    sum + sign * a * (k as f32 * x).cos()
+                })
+            })
+            .collect()
+    }
+}
+
+impl FromStr for WindowFunction {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "rectangular" => Ok(WindowFunction::Rectangular),
+            "hann" => Ok(WindowFunction::Hann),
+            "hamming" => Ok(WindowFunction::Hamming),
+            "blackman" => Ok(WindowFunction::Blackman),
+            "blackman-harris" => Ok(WindowFunction::BlackmanHarris),
+            "flat-top" => Ok(WindowFunction::FlatTop),
+            _ => Err(format!(
+                "Unknown window \"{}\", expected rectangular, hann, hamming, blackman, blackman-harris or flat-top",
+                s
+            )),
+        }
+    }
+}
+
+impl fmt::Display for WindowFunction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            WindowFunction::Rectangular => "rectangular",
+            WindowFunction::Hann => "hann",
+            WindowFunction::Hamming => "hamming",
+            WindowFunction::Blackman => "blackman",
+            WindowFunction::BlackmanHarris => "blackman-harris",
+            WindowFunction::FlatTop => "flat-top",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn windows_are_symmetric_and_peak_in_the_middle() {
+        let mut window = WindowFunction::Rectangular;
+        loop {
+            let coefficients = window.coefficients(65);
+            for n in 0..65 {
+                assert!((coefficients[n] - coefficients[64 - n]).abs() < 1e-5, "{} isn't symmetric", window);
+            }
+            assert!((coefficients[32] - 1.0).abs() < 1e-3, "{} peaks at {}", window, coefficients[32]);
+
+            window = window.next();
+            if window == WindowFunction::Rectangular {
+                break;
+            }
+        }
+    }
+
+    #[test]
+    fn names_round_trip() {
+        let window = WindowFunction::BlackmanHarris;
+        assert_eq!(window.to_string().parse::<WindowFunction>(), Ok(window));
+        assert!("kaiser".parse::<WindowFunction>().is_err());
+    }
+}
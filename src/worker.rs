@@ -0,0 +1,237 @@
+use std::collections::VecDeque;
+use std::mem;
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread::{self, JoinHandle};
+
+use crate::channels::MultiChannelAnalyzer;
+use crate::SpectrumAnalyzer;
+
+// One STFT column
+#[derive(Clone, Debug, PartialEq)]
+pub struct Frame {
+    // When the middle of the column falls, in seconds of input since the worker started
+    pub time: f64,
+    // One spectrum for each signal the analyzer picks out of the input
+    pub spectra: Vec<Vec<f32>>,
+}
+
+// Runs a `MultiChannelAnalyzer` on a thread of its own, so big FFTs don't hold up whoever feeds it
+// Samples go in with `push_samples`, and every frame they make comes back out of `take_frames`
+pub struct AnalysisWorker {
+    shared: Arc<Shared>,
+    thread: Option<JoinHandle<()>>,
+    // Bumped whenever the analyzer is replaced, so frames from the old one can be told apart
+    generation: u64,
+    // A copy of the analyzer the worker runs, never fed, for answering questions about bins and frequencies
+    layout: SpectrumAnalyzer,
+    signals: usize,
+    channels: usize,
+}
+
+struct Shared {
+    pending: Mutex<Pending>,
+    // Wakes the worker when there's something pending
+    wake: Condvar,
+    // Frames made by the worker, tagged with the generation of the analyzer that made them
+    frames: Mutex<VecDeque<(u64, Frame)>>,
+}
+
+// What's waiting for the worker to pick up
+#[derive(Default)]
+struct Pending {
+    samples: Vec<f32>,
+    analyzer: Option<(u64, MultiChannelAnalyzer)>,
+    stop: bool,
+}
+
+impl AnalysisWorker {
+    pub fn new(analyzer: MultiChannelAnalyzer) -> Self {
+        let shared = Arc::new(Shared {
+            pending: Mutex::new(Pending::default()),
+            wake: Condvar::new(),
+            frames: Mutex::new(VecDeque::new()),
+        });
+        let mut worker = AnalysisWorker {
+            shared: shared.clone(),
+            thread: None,
+            generation: 0,
+            layout: analyzer.first().clone(),
+            signals: analyzer.signals(),
+            channels: analyzer.channels(),
+        };
+
+        let thread = thread::Builder::new()
+            .name("analysis".to_string())
+            .spawn(move || run(shared, analyzer))
+            .expect("Couldn't start the analysis thread");
+        worker.thread = Some(thread);
+        worker
+    }
+
+    // Analyse with `analyzer` from now on. Frames the old one made that haven't been taken yet
+    // are thrown away, since they won't fit with the new ones
+    pub fn replace(&mut self, analyzer: MultiChannelAnalyzer) {
+        self.generation += 1;
+        self.layout = analyzer.first().clone();
+        self.signals = analyzer.signals();
+        self.channels = analyzer.channels();
+
+        self.shared.pending.lock().unwrap().analyzer = Some((self.generation, analyzer));
+        self.shared.wake.notify_one();
+    }
+
+    // `samples` are interleaved by channel, like `MultiChannelAnalyzer::push_samples` takes them
+    pub fn push_samples(&self, samples: &[f32]) {
+        if samples.is_empty() {
+            return;
+        }
+        self.shared.pending.lock().unwrap().samples.extend_from_slice(samples);
+        self.shared.wake.notify_one();
+    }
+
+    // Append every frame made since the last call to `out`, the oldest first
+    pub fn take_frames(&self, out: &mut Vec<Frame>) {
+        let mut frames = self.shared.frames.lock().unwrap();
+        out.extend(
+            frames
+                .drain(..)
+                .filter(|(generation, _)| *generation == self.generation)
+                .map(|(_, frame)| frame),
+        );
+    }
+
+    pub fn bins(&self) -> usize {
+        self.layout.bins()
+    }
+
+    pub fn bin_width(&self) -> f32 {
+        self.layout.bin_width()
+    }
+
+    pub fn sample_rate(&self) -> u32 {
+        self.layout.sample_rate()
+    }
+
+    pub fn frame_interval(&self) -> f32 {
+        self.layout.frame_interval()
+    }
+
+    // How many spectra each frame holds
+    pub fn signals(&self) -> usize {
+        self.signals
+    }
+
+    // How many channels the input has
+    pub fn channels(&self) -> usize {
+        self.channels
+    }
+}
+
+impl Drop for AnalysisWorker {
+    fn drop(&mut self) {
+        self.shared.pending.lock().unwrap().stop = true;
+        self.shared.wake.notify_one();
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+// The worker thread: wait for samples, analyse them, and hand the frames back, until told to stop
+fn run(shared: Arc<Shared>, mut analyzer: MultiChannelAnalyzer) {
+    let mut generation = 0;
+    let mut samples = Vec::new();
+    // Seconds of input seen so far, and when the current analyzer started on it
+    let mut input_time = 0.0;
+    let mut start_time = 0.0;
+    // Frames the current analyzer has made
+    let mut index = 0;
+
+    loop {
+        {
+            let mut pending = shared.pending.lock().unwrap();
+            while pending.samples.is_empty() && pending.analyzer.is_none() && !pending.stop {
+                pending = shared.wake.wait(pending).unwrap();
+            }
+            if pending.stop {
+                return;
+            }
+            if let Some((new_generation, new_analyzer)) = pending.analyzer.take() {
+                generation = new_generation;
+                analyzer = new_analyzer;
+                start_time = input_time;
+                index = 0;
+            }
+            // Swap buffers rather than copying, so the lock is only held for a moment
+            samples.clear();
+            mem::swap(&mut samples, &mut pending.samples);
+        }
+
+        analyzer.push_samples(&samples);
+        input_time += samples.len() as f64 / (analyzer.channels().max(1) as f64 * analyzer.sample_rate() as f64);
+
+        let mut spectra = vec![vec![0.0; analyzer.bins()]; analyzer.signals()];
+        let mut made = Vec::new();
+        while analyzer.next_frames(&mut spectra) {
+            let time = start_time + analyzer.first().frame_center(index);
+            made.push((generation, Frame { time, spectra: spectra.clone() }));
+            index += 1;
+        }
+        if !made.is_empty() {
+            shared.frames.lock().unwrap().extend(made);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{ChannelMode, WindowFunction};
+    use std::time::{Duration, Instant};
+
+    fn analyzer(fft_size: usize) -> MultiChannelAnalyzer {
+        let analyzer = SpectrumAnalyzer::new(fft_size, fft_size / 2, 8000, WindowFunction::Hann);
+        MultiChannelAnalyzer::new(ChannelMode::Mix, 1, analyzer)
+    }
+
+    // Keep taking frames until there are `count` of them, or give up after a second
+    fn wait_for(worker: &AnalysisWorker, count: usize) -> Vec<Frame> {
+        let start = Instant::now();
+        let mut frames = Vec::new();
+        while frames.len() < count && start.elapsed() < Duration::from_secs(1) {
+            worker.take_frames(&mut frames);
+            thread::sleep(Duration::from_millis(1));
+        }
+        frames
+    }
+
+    #[test]
+    fn every_frame_comes_back() {
+        let worker = AnalysisWorker::new(analyzer(256));
+        // Enough for the first window and 9 hops after it, in uneven pieces
+        let samples = vec![0.5; 256 + 9 * 128];
+        for piece in samples.chunks(100) {
+            worker.push_samples(piece);
+        }
+
+        let frames = wait_for(&worker, 10);
+        assert_eq!(frames.len(), 10);
+        assert!(frames.iter().all(|frame| frame.spectra.len() == 1 && frame.spectra[0].len() == 128));
+        // Every hop is 128 / 8000 seconds on from the last
+        assert_eq!(frames[0].time, 128.0 / 8000.0);
+        assert!((frames[9].time - frames[0].time - 9.0 * 0.016).abs() < 1e-9);
+    }
+
+    #[test]
+    fn frames_from_a_replaced_analyzer_are_dropped() {
+        let mut worker = AnalysisWorker::new(analyzer(256));
+        worker.push_samples(&[0.5; 512]);
+        worker.replace(analyzer(128));
+        worker.push_samples(&[0.5; 128]);
+
+        let frames = wait_for(&worker, 1);
+        assert!(!frames.is_empty());
+        assert!(frames.iter().all(|frame| frame.spectra[0].len() == 64));
+        assert_eq!(worker.bins(), 64);
+    }
+}